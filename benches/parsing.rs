@@ -0,0 +1,231 @@
+// Benchmarks for the parsing hot paths, run with `cargo bench`. Pass a name to only run the benchmarks that contain it (ex. `cargo bench -- package`).
+// This is a small stand-in for criterion so the crate needs no extra dependencies. Each benchmark runs for a fixed time and prints its mean, there are no statistics or saved baselines, so checking for a regression means comparing the printed numbers from two runs by hand.
+
+use nix_update_report::{
+    Flake, FlakeCompareData, FlakeCompareOptions, NameFilter, Nixpkgs, Package,
+};
+use regex::Regex;
+use serde_json::{Map, Value, json};
+use std::{
+    env,
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+/// How long each benchmark is run for after warming up
+const MEASURE_TIME: Duration = Duration::from_secs(3);
+
+/// How long each benchmark is run for before measuring, so caches and the regex statics are warm
+const WARMUP_TIME: Duration = Duration::from_millis(500);
+
+// --- INPUTS
+/// Package names seen in nixpkgs, mixed with package sets so attribute paths are split too
+const PKG_NAMES: [&str; 24] = [
+    "hello",
+    "firefox-unwrapped",
+    "linux_6_6",
+    "python3Packages.requests",
+    "python3Packages.numpy",
+    "python312Packages.django",
+    "haskellPackages.pandoc",
+    "nodePackages.typescript",
+    "vimPlugins.nvim-treesitter",
+    "rubyPackages.nokogiri",
+    "perlPackages.DBI",
+    "ocamlPackages.dune_3",
+    "gnome.nautilus",
+    "kdePackages.kate",
+    "terraform-providers.aws",
+    "rust-analyzer-unwrapped",
+    "openssl_3",
+    "gcc13",
+    "nerdfonts",
+    "jetbrains.idea-community",
+    "vscode-extensions.ms-python.python",
+    "home-assistant-custom-components.frigate",
+    "texlivePackages.latexmk",
+    "emacsPackages.magit",
+];
+
+/// Version changes in the forms nixpkgs commits use, as (old, new)
+const VERSION_CHANGES: [(&str, &str); 8] = [
+    ("1.2.3", "1.2.4"),
+    ("2.31.0", "2.32.3"),
+    ("6.6.30", "6.6.31"),
+    ("125.0.3", "126.0"),
+    ("0-unstable-2024-04-01", "0-unstable-2024-05-20"),
+    ("2024.03.12", "2024.05.01"),
+    ("3.0.0rc1", "3.0.0"),
+    ("1.78.0", "1.79.0"),
+];
+
+/// A unique tag for the nth generated package (a, b, ..., z, ba, ...), only letters so it never reads as part of a version
+fn unique_tag(mut n: usize) -> String {
+    let mut letters = vec![];
+    loop {
+        letters.push((b'a' + (n % 26) as u8) as char);
+        n /= 26;
+        if n == 0 {
+            break;
+        }
+    }
+    letters.iter().rev().collect()
+}
+
+/// The commit messages of a large nixpkgs channel bump. Most are single updates like r-ryantm makes, mixed with inits, drops, backports, grouped updates, and the merge and module commits that can't be parsed.
+fn commit_messages(count: usize) -> Vec<String> {
+    (0..count)
+        .map(|i| {
+            let name = format!(
+                "{}-{}",
+                PKG_NAMES[i % PKG_NAMES.len()],
+                unique_tag(i / PKG_NAMES.len())
+            );
+            let other = format!("{}-unwrapped", name);
+            let (old, new) = VERSION_CHANGES[i % VERSION_CHANGES.len()];
+            match i % 20 {
+                0 => format!("{}: init at {}", name, new),
+                1 => format!("{}: drop", name),
+                2 => format!("[24.05] {}: {} -> {}", name, old, new),
+                3 => format!("{},{}: {} -> {}", name, other, old, new),
+                4 => format!(
+                    "Merge pull request #{} from r-ryantm/auto-update/{}",
+                    300000 + i,
+                    name
+                ),
+                5 => format!("nixos/{}: add settings option", name),
+                6 => "treewide: remove unused inputs".into(),
+                7 => format!("{}: {} -> {} (#{})", name, old, new, 300000 + i),
+                _ => format!("{}: {} -> {}", name, old, new),
+            }
+        })
+        .collect()
+}
+
+/// Full derivation names as `nix flake show` gives them, with pre-releases, unstable dates, and names holding numbers
+fn derivation_names(count: usize) -> Vec<String> {
+    const NAMES: [&str; 12] = [
+        "hello-2.12.1",
+        "python3.12-requests-2.32.3",
+        "firefox-unwrapped-126.0",
+        "linux-6.6.31",
+        "openssl-3.0.14",
+        "glibc-2.39-52",
+        "vim-plugin-nvim-treesitter-0-unstable-2024-05-20",
+        "gcc-wrapper-13.2.0",
+        "rust-analyzer-unwrapped-2024-05-13",
+        "nodejs-slim-22.2.0",
+        "postgresql-16.3",
+        "ghc-9.6.5-with-packages",
+    ];
+    (0..count)
+        .map(|i| format!("{}-{}", unique_tag(i / NAMES.len()), NAMES[i % NAMES.len()]))
+        .collect()
+}
+
+/// `nix flake show --json` output for a flake with the same packages on every arch. The revision picks the versions, so two revisions differ the way a flake update does: most packages bumped, some dropped, and some added.
+fn flake_json(archs: &[&str], pkgs: usize, revision: usize) -> Value {
+    let mut packages = Map::new();
+    for arch in archs {
+        let mut arch_pkgs = Map::new();
+        for i in 0..pkgs {
+            // Every sixth package only exists in one of the revisions
+            if i % 6 == revision % 2 {
+                continue;
+            }
+            let attr = format!(
+                "{}-{}",
+                PKG_NAMES[i % PKG_NAMES.len()].replace('.', "-"),
+                unique_tag(i)
+            );
+            let version = match i % 3 {
+                0 => format!("{}.{}.0", 1 + i % 7, revision),
+                1 => format!("0-unstable-2024-0{}-1{}", 1 + revision % 9, i % 10),
+                _ => format!("{}.{}", 1 + i % 4, i % 5),
+            };
+            arch_pkgs.insert(
+                attr.clone(),
+                json!({
+                    "name": format!("{}-{}", attr, version),
+                    "description": format!("The {} package", attr),
+                    "type": "derivation",
+                }),
+            );
+        }
+        packages.insert((*arch).into(), Value::Object(arch_pkgs));
+    }
+    json!({ "packages": packages })
+}
+
+// --- HARNESS
+/// Runs a benchmark for MEASURE_TIME and prints the mean time per run, unless the filter leaves it out
+fn bench<T>(filter: &Option<String>, name: &str, mut run: impl FnMut() -> T) {
+    if filter
+        .as_ref()
+        .is_some_and(|val| !name.contains(val.as_str()))
+    {
+        return;
+    }
+
+    let start = Instant::now();
+    while start.elapsed() < WARMUP_TIME {
+        black_box(run());
+    }
+
+    let mut runs: u32 = 0;
+    let start = Instant::now();
+    while start.elapsed() < MEASURE_TIME {
+        black_box(run());
+        runs += 1;
+    }
+
+    println!(
+        "{:<32} {:>12.3?}/run ({} runs)",
+        name,
+        start.elapsed() / runs,
+        runs
+    );
+}
+
+fn main() {
+    // Cargo passes --bench, the first other argument is the filter
+    let filter = env::args().skip(1).find(|val| !val.starts_with("--"));
+
+    let commits = commit_messages(5000);
+    bench(&filter, "nixpkgs_commits_5000", || {
+        Nixpkgs::new(black_box(&commits))
+    });
+
+    let names = derivation_names(10000);
+    bench(&filter, "package_names_10000", || {
+        names
+            .iter()
+            .map(|val| Package::new(black_box(val), &None))
+            .collect::<Vec<Package>>()
+    });
+
+    let archs = [
+        "x86_64-linux",
+        "aarch64-linux",
+        "x86_64-darwin",
+        "aarch64-darwin",
+    ];
+    let old = Flake::new(&flake_json(&archs, 3000, 1)).unwrap();
+    let new = Flake::new(&flake_json(&archs, 3000, 2)).unwrap();
+    bench(&filter, "flake_compare_4x3000", || {
+        FlakeCompareData::new(black_box(&old), black_box(&new))
+    });
+
+    // A filter with the coverage check, which looks at every package of the new flake again
+    let options = FlakeCompareOptions {
+        check_coverage: true,
+        filter: NameFilter {
+            include: None,
+            exclude: Some(Regex::new("^python").unwrap()),
+        },
+        ..Default::default()
+    };
+    bench(&filter, "flake_compare_coverage_4x3000", || {
+        FlakeCompareData::new_with_options(black_box(&old), black_box(&new), &options)
+    });
+}
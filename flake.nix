@@ -25,6 +25,13 @@
 
           src = ./.;
           cargoLock.lockFile = ./Cargo.lock;
+
+          # The rest backend and webhooks call curl, so it is always on the PATH
+          nativeBuildInputs = [ pkgs.makeWrapper ];
+          postInstall = ''
+            wrapProgram $out/bin/${cargoToml.package.name} \
+              --prefix PATH : ${pkgs.lib.makeBinPath [ pkgs.curl ]}
+          '';
         };
       }
     ));
@@ -0,0 +1,241 @@
+// Shell completion scripts generated from the clap command tree
+
+use clap::{Arg, Command, ValueEnum};
+
+/// The shells completion scripts can be generated for
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shell {
+    /// Bash completions (source the output or put it in bash-completion's directory)
+    Bash,
+    /// Zsh completions (uses bashcompinit)
+    Zsh,
+    /// Fish completions
+    Fish,
+    /// PowerShell completions
+    Powershell,
+}
+
+/// A flag a subcommand accepts, flattened out of clap's arg data
+struct Flag {
+    long: Option<String>,
+    short: Option<char>,
+    help: String,
+    /// Does the flag need a value after it
+    takes_value: bool,
+    /// The values the flag accepts if it is an enum
+    values: Vec<String>,
+}
+
+impl Flag {
+    fn new(arg: &Arg) -> Flag {
+        Flag {
+            long: arg.get_long().map(|val| val.into()),
+            short: arg.get_short(),
+            help: arg
+                .get_help()
+                .map_or("".into(), |val| val.to_string().replace(['\'', '"'], "")),
+            takes_value: arg.get_action().takes_values(),
+            values: arg
+                .get_possible_values()
+                .iter()
+                .filter(|val| !val.is_hide_set())
+                .map(|val| val.get_name().into())
+                .collect(),
+        }
+    }
+
+    /// All the spellings of this flag (ex. --out and -o)
+    fn spellings(&self) -> Vec<String> {
+        self.long
+            .iter()
+            .map(|val| format!("--{}", val))
+            .chain(self.short.iter().map(|val| format!("-{}", val)))
+            .collect()
+    }
+}
+
+/// Grab the visible flags of a command
+fn flags(cmd: &Command) -> Vec<Flag> {
+    cmd.get_arguments()
+        .filter(|arg| !arg.is_positional() && !arg.is_hide_set())
+        .map(Flag::new)
+        .collect()
+}
+
+/// Generate a completion script for the shell
+pub fn generate(shell: Shell, mut cmd: Command) -> String {
+    cmd.build();
+    match shell {
+        Shell::Bash => bash(&cmd),
+        // zsh can load bash completions through bashcompinit
+        Shell::Zsh => format!(
+            "#compdef {}\n\
+            autoload -U +X bashcompinit && bashcompinit\n\
+            {}",
+            cmd.get_name(),
+            bash(&cmd)
+        ),
+        Shell::Fish => fish(&cmd),
+        Shell::Powershell => powershell(&cmd),
+    }
+}
+
+fn bash(cmd: &Command) -> String {
+    let name = cmd.get_name();
+    let func = format!("_{}", name.replace('-', "_"));
+
+    let top_words: Vec<String> = cmd
+        .get_subcommands()
+        .map(|sub| sub.get_name().to_string())
+        .chain(flags(cmd).iter().flat_map(|flag| flag.spellings()))
+        .collect();
+
+    let sub_cases: String = cmd
+        .get_subcommands()
+        .map(|sub| {
+            let sub_flags = flags(sub);
+            let value_cases: String = sub_flags
+                .iter()
+                .filter(|flag| flag.takes_value)
+                .map(|flag| {
+                    let reply = match flag.values.is_empty() {
+                        true => "COMPREPLY=($(compgen -f -- \"$cur\"))".to_string(),
+                        false => format!(
+                            "COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))",
+                            flag.values.join(" ")
+                        ),
+                    };
+                    format!(
+                        "                {})\n                    {}\n                    return\n                    ;;\n",
+                        flag.spellings().join("|"),
+                        reply
+                    )
+                })
+                .collect();
+
+            format!(
+                "        {})\n            case \"$prev\" in\n{}            esac\n            COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n            ;;\n",
+                sub.get_name(),
+                value_cases,
+                sub_flags
+                    .iter()
+                    .flat_map(|flag| flag.spellings())
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            )
+        })
+        .collect();
+
+    format!(
+        "{func}() {{\n\
+        \x20   local cur prev\n\
+        \x20   cur=\"${{COMP_WORDS[COMP_CWORD]}}\"\n\
+        \x20   prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"\n\
+        \n\
+        \x20   if [[ $COMP_CWORD -eq 1 ]]; then\n\
+        \x20       COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n\
+        \x20       return\n\
+        \x20   fi\n\
+        \n\
+        \x20   case \"${{COMP_WORDS[1]}}\" in\n\
+        {}\
+        \x20   esac\n\
+        }}\n\
+        \n\
+        complete -F {func} -o bashdefault -o default {name}\n",
+        top_words.join(" "),
+        sub_cases,
+    )
+}
+
+fn fish(cmd: &Command) -> String {
+    let name = cmd.get_name();
+    let subs: Vec<&str> = cmd.get_subcommands().map(|sub| sub.get_name()).collect();
+
+    let flag_line = |flag: &Flag, condition: &str| {
+        let mut line = format!("complete -c {} -n \"{}\"", name, condition);
+        if let Some(long) = &flag.long {
+            line.push_str(&format!(" -l {}", long));
+        }
+        if let Some(short) = flag.short {
+            line.push_str(&format!(" -s {}", short));
+        }
+        if flag.takes_value {
+            line.push_str(" -r");
+        }
+        if !flag.values.is_empty() {
+            line.push_str(&format!(" -f -a \"{}\"", flag.values.join(" ")));
+        }
+        line.push_str(&format!(" -d '{}'\n", flag.help));
+        line
+    };
+
+    let mut script: String = flags(cmd)
+        .iter()
+        .map(|flag| flag_line(flag, "__fish_use_subcommand"))
+        .collect();
+
+    for sub in cmd.get_subcommands() {
+        script.push_str(&format!(
+            "complete -c {} -n \"__fish_use_subcommand\" -f -a \"{}\" -d '{}'\n",
+            name,
+            sub.get_name(),
+            sub.get_about()
+                .map_or("".into(), |val| val.to_string().replace(['\'', '"'], ""))
+        ));
+
+        let condition = format!("__fish_seen_subcommand_from {}", sub.get_name());
+        for flag in flags(sub) {
+            script.push_str(&flag_line(&flag, &condition));
+        }
+    }
+
+    // Stop offering subcommands once one was given
+    script.push_str(&format!(
+        "complete -c {} -n \"__fish_seen_subcommand_from {}\" -F\n",
+        name,
+        subs.join(" ")
+    ));
+
+    script
+}
+
+fn powershell(cmd: &Command) -> String {
+    let name = cmd.get_name();
+    let words = |cmd: &Command| {
+        flags(cmd)
+            .iter()
+            .flat_map(|flag| flag.spellings())
+            .map(|val| format!("'{}'", val))
+            .collect::<Vec<String>>()
+            .join(", ")
+    };
+
+    let sub_entries: String = cmd
+        .get_subcommands()
+        .map(|sub| format!("        '{}' = @({})\n", sub.get_name(), words(sub)))
+        .collect();
+
+    format!(
+        "Register-ArgumentCompleter -Native -CommandName '{name}' -ScriptBlock {{\n\
+        \x20   param($wordToComplete, $commandAst, $cursorPosition)\n\
+        \n\
+        \x20   $subcommands = @{{\n\
+        {}\
+        \x20   }}\n\
+        \x20   $elements = $commandAst.CommandElements | ForEach-Object {{ $_.ToString() }}\n\
+        \n\
+        \x20   if ($elements.Count -gt 1 -and $subcommands.ContainsKey($elements[1]) -and $elements[1] -ne $wordToComplete) {{\n\
+        \x20       $candidates = $subcommands[$elements[1]]\n\
+        \x20   }} else {{\n\
+        \x20       $candidates = @($subcommands.Keys) + @({})\n\
+        \x20   }}\n\
+        \n\
+        \x20   $candidates | Where-Object {{ $_ -like \"$wordToComplete*\" }} | ForEach-Object {{\n\
+        \x20       [System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterValue', $_)\n\
+        \x20   }}\n\
+        }}\n",
+        sub_entries,
+        words(cmd),
+    )
+}
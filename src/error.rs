@@ -0,0 +1,65 @@
+// The error type for everything that can stop a report from being made
+
+use std::{error, fmt, io};
+
+/// Everything that can stop a report from being made. The message says what went wrong and where (ex. the path or command).
+#[derive(Debug)]
+pub enum Error {
+    /// Text that could not be parsed, holds where it came from (ex. a path or flake url) and why
+    Parse { what: String, message: String },
+    /// A command or request that could not be run, failed, or timed out, holds the command and why
+    Command { command: String, message: String },
+    /// A file that could not be read or written (stdin and stdout use - as the path)
+    Io { path: String, error: io::Error },
+    /// Arguments or input that were rejected before any work was done
+    Invalid(String),
+    /// The report was made but breaks checks asked for on the command line (ex. --fail-on-removed), holds one line per broken check
+    Check(Vec<String>),
+}
+
+impl Error {
+    /// Makes a parse error for text from where
+    pub fn parse(what: &str, message: impl fmt::Display) -> Error {
+        Error::Parse {
+            what: what.into(),
+            message: message.to_string(),
+        }
+    }
+
+    /// Makes an error for a command that failed, trimming the message since it is usually the command's stderr
+    pub fn command(command: &str, message: impl fmt::Display) -> Error {
+        Error::Command {
+            command: command.into(),
+            message: message.to_string().trim().into(),
+        }
+    }
+
+    /// Makes an error for a file that could not be read or written
+    pub fn io(path: &str, error: io::Error) -> Error {
+        Error::Io {
+            path: path.into(),
+            error,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Parse { what, message } => write!(f, "{}: {}", what, message),
+            Error::Command { command, message } => write!(f, "{}: {}", command, message),
+            Error::Io { path, error } => write!(f, "{}: {}", path, error),
+            Error::Invalid(message) => write!(f, "{}", message),
+            Error::Check(failures) => write!(f, "{}", failures.join("\n")),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
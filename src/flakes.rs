@@ -1,11 +1,18 @@
 // All the structs used to organize package data when using the flake command
 
-#[path = "packages.rs"]
-mod packages;
-
-use packages::{Package, PkgCompareData};
+use crate::html;
+use crate::log::debug;
+use crate::packages::{
+    BumpLevel, NameFilter, Package, PkgCompareData, UpdateOrder, VersionDirection, pair_renames,
+};
+use crate::template::{self, TemplateContext, limit_list, net_change, percent};
+use crate::webhook::{self, WebhookStyle};
 use serde_json::Value;
-use std::collections::{HashMap, HashSet};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    error, fmt,
+};
 
 // --- TYPE ALIASES
 type PkgMap = HashMap<String, Package>;
@@ -15,144 +22,574 @@ type PkgMap = HashMap<String, Package>;
 #[derive(PartialEq, Eq)]
 pub struct Flake(HashMap<String, PkgMap>);
 
+/// The per-arch flake outputs that can be read and compared
+#[derive(Default, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum FlakeOutput {
+    /// The packages output (falls back to legacyPackages)
+    #[default]
+    Packages,
+    /// The apps output, which usually has no versions
+    Apps,
+    /// The checks output
+    Checks,
+    /// The devShells output
+    DevShells,
+}
+
+impl FlakeOutput {
+    /// The name of the output in the flake json
+    pub fn attr(&self) -> &'static str {
+        match self {
+            FlakeOutput::Packages => "packages",
+            FlakeOutput::Apps => "apps",
+            FlakeOutput::Checks => "checks",
+            FlakeOutput::DevShells => "devShells",
+        }
+    }
+}
+
+/// Options that change which outputs a Flake is read from
+#[derive(Default, Clone, Debug)]
+pub struct FlakeOptions {
+    /// Also read the legacyPackages output (packages win when both have the same name)
+    pub legacy_packages: bool,
+    /// The output to read, legacy_packages only applies to packages
+    pub output: FlakeOutput,
+}
+
 impl Flake {
-    pub fn new(flake_json: &Value) -> Flake {
+    /// Reads a flake from `nix flake show --json` output. Package entries without a name use their attribute key instead, and entries that still can't be parsed become unparsable packages instead of failing the whole flake. Falls back to legacyPackages when packages is missing or empty.
+    pub fn new(flake_json: &Value) -> Result<Flake, FlakeParseError> {
+        Flake::new_with_options(flake_json, &FlakeOptions::default())
+    }
+
+    /// Reads a flake from the raw text `nix flake show --json` prints, see Flake::new
+    pub fn from_json_str(json: &str) -> Result<Flake, FlakeParseError> {
+        let flake_json: Value = serde_json::from_str(json)
+            .map_err(|err| FlakeParseError::InvalidJson(err.to_string()))?;
+        Flake::new(&flake_json)
+    }
+
+    /// Reads a flake from `nix flake show --json` output using the options given
+    pub fn new_with_options(
+        flake_json: &Value,
+        options: &FlakeOptions,
+    ) -> Result<Flake, FlakeParseError> {
+        // Other outputs have no legacy fallback, so they are read as they are
+        if options.output != FlakeOutput::Packages {
+            let attr = options.output.attr();
+            let mut new_fp = flake_json
+                .get(attr)
+                .map(|output| Flake::read_output(output, false, true))
+                .transpose()?
+                .ok_or_else(|| FlakeParseError::MissingOutput(attr.into()))?;
+
+            new_fp.retain(|_, pkgs| !pkgs.is_empty());
+            return Ok(Flake(new_fp));
+        }
+
+        let packages = flake_json
+            .get("packages")
+            .map(|output| Flake::read_output(output, false, false))
+            .transpose()?;
+
+        // Only touch legacyPackages if asked to or if there is nothing else to read
+        let use_legacy = options.legacy_packages
+            || packages
+                .as_ref()
+                .is_none_or(|val| val.values().all(|pkgs| pkgs.is_empty()));
+        let legacy_packages = match use_legacy {
+            true => flake_json
+                .get("legacyPackages")
+                .map(|output| Flake::read_output(output, true, false))
+                .transpose()?,
+            false => None,
+        };
+
+        let mut new_fp = match (packages, legacy_packages) {
+            (None, None) => return Err(FlakeParseError::MissingPackages),
+            (Some(packages), None) => packages,
+            (None, Some(legacy_packages)) => legacy_packages,
+            (Some(packages), Some(mut legacy_packages)) => {
+                for (arch, pkgs) in packages {
+                    legacy_packages.entry(arch).or_default().extend(pkgs);
+                }
+                legacy_packages
+            }
+        };
+
+        new_fp.retain(|_, pkgs| !pkgs.is_empty());
+        Ok(Flake(new_fp))
+    }
+
+    /// Copies the flake keeping only the packages whose name the filter keeps. Archs are kept even if they end up empty.
+    pub fn filtered(&self, filter: &NameFilter) -> Flake {
+        Flake(
+            self.0
+                .iter()
+                .map(|(arch, pkgs)| {
+                    (
+                        arch.clone(),
+                        pkgs.iter()
+                            .filter(|(name, _)| filter.matches(name))
+                            .map(|(name, pkg)| (name.clone(), pkg.clone()))
+                            .collect(),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    /// Drops every arch that isn't in the list, returning the archs in the list the flake doesn't have
+    pub fn retain_archs(&mut self, archs: &[String]) -> Vec<String> {
+        self.0.retain(|arch, _| archs.contains(arch));
+        archs
+            .iter()
+            .filter(|arch| !self.0.contains_key(*arch))
+            .cloned()
+            .collect()
+    }
+
+    /// Finds the mappings this (old) flake can't use since it already has a package under the new name, as (old name, new name) sorted by old name. Comparing them would match that new package twice, so the comparison skips them.
+    pub fn taken_mappings(&self, name_map: &HashMap<String, String>) -> Vec<(String, String)> {
+        let mut taken: Vec<(String, String)> = name_map
+            .iter()
+            .filter(|(old_name, new_name)| {
+                self.0.values().any(|pkgs| {
+                    pkgs.contains_key(*old_name) && mapping_taken(pkgs, name_map, new_name)
+                })
+            })
+            .map(|(old_name, new_name)| (old_name.clone(), new_name.clone()))
+            .collect();
+        taken.sort();
+        taken
+    }
+
+    /// Finds the packages that are missing from some of the flake's archs as (name, archs missing it), sorted by name
+    pub fn coverage_gaps(&self) -> Vec<(String, Vec<String>)> {
+        self.coverage_below(self.0.len())
+    }
+
+    /// Finds the packages that are on fewer than min_archs of the flake's archs as (name, archs missing it), sorted by name
+    pub fn coverage_below(&self, min_archs: usize) -> Vec<(String, Vec<String>)> {
+        self.coverage_below_matching(min_archs, &NameFilter::default())
+    }
+
+    /// Finds the packages the filter keeps that are on fewer than min_archs of the flake's archs, see Flake::coverage_below. The same as filtering the flake first, without copying it.
+    fn coverage_below_matching(
+        &self,
+        min_archs: usize,
+        filter: &NameFilter,
+    ) -> Vec<(String, Vec<String>)> {
+        let mut archs: Vec<&String> = self.0.keys().collect();
+        archs.sort();
+
+        let names: BTreeSet<&String> = self
+            .0
+            .values()
+            .flat_map(|pkgs| pkgs.keys())
+            .filter(|name| filter.matches(name))
+            .collect();
+        names
+            .into_iter()
+            .filter_map(|name| {
+                let missing: Vec<String> = archs
+                    .iter()
+                    .filter(|arch| !self.0[**arch].contains_key(name))
+                    .map(|arch| (*arch).clone())
+                    .collect();
+                (archs.len() - missing.len() < min_archs).then(|| (name.clone(), missing))
+            })
+            .collect()
+    }
+
+    /// Render every arch's packages as a markdown inventory showing how each one was parsed, sorted by arch and name
+    pub fn inventory(&self, heading: &str) -> String {
+        let sorted: BTreeMap<&String, BTreeMap<&String, &Package>> = self
+            .0
+            .iter()
+            .map(|(arch, pkgs)| (arch, pkgs.iter().collect()))
+            .collect();
+
+        sorted.iter().fold(
+            format!(
+                "## {}\n\
+                Report generated using [`nix-update-report`](https://github.com/aldenparker/nix-update-report.git).\n",
+                heading
+            ),
+            |mut acc, (arch, pkgs)| {
+                acc.push_str(&format!(
+                    "\n### {} ({} packages, {} unparsable)\n",
+                    arch,
+                    pkgs.len(),
+                    pkgs.values()
+                        .filter(|pkg| matches!(pkg, Package::Unparsable(_)))
+                        .count()
+                ));
+                for pkg in pkgs.values() {
+                    acc.push_str(&match pkg {
+                        Package::Parsed { name, version, .. } => {
+                            format!(" - {}: {}\n", name, version)
+                        }
+                        Package::Unparsable(name) => format!(" - {}: unparsable\n", name),
+                    });
+                }
+                acc
+            },
+        )
+    }
+
+    /// Render every arch's packages as pretty printed json, by arch and then by name
+    pub fn to_json(&self) -> String {
+        let sorted: BTreeMap<&String, BTreeMap<&String, &Package>> = self
+            .0
+            .iter()
+            .map(|(arch, pkgs)| (arch, pkgs.iter().collect()))
+            .collect();
+        serde_json::to_string_pretty(&sorted).expect("Unable to serialize flake")
+    }
+
+    /// Reads a single `<arch>.<attr>` output into package maps. Legacy outputs skip entries that are not derivations since they hold nested package sets. With by_attr the attribute is the package's name instead of its derivation name, since apps, checks, and devShells often share one (ex. every mkShell is nix-shell).
+    fn read_output(
+        output: &Value,
+        legacy: bool,
+        by_attr: bool,
+    ) -> Result<HashMap<String, PkgMap>, FlakeParseError> {
         let mut new_fp: HashMap<String, PkgMap> = HashMap::new();
-        for (arch, pkgs) in flake_json["packages"]
+        for (arch, pkgs) in output
             .as_object()
-            .expect("Malformed json, only flake json with a packages output are acceptable")
+            .ok_or(FlakeParseError::MissingPackages)?
             .iter()
         {
-            if pkgs.to_string() != "{}" {
-                let mut new_ps: PkgMap = PkgMap::new();
-                for (_, pkg_value) in pkgs
-                    .as_object()
-                    .expect("Malformed json, only flake json with a packages output are acceptable")
-                    .iter()
-                {
-                    let new_pkg: Package = Package::new(
-                    &pkg_value["name"]
-                        .as_str()
-                        .expect(
-                            "Malformed json, only flake json with a packages output are acceptable",
-                        )
-                        .into(),
-                    &pkg_value["description"].as_str().map_or(None, |val| {
-                            if val == "" {
-                                return None;
-                            }
-
-                            Some(val.into())
-                        })
-                    );
+            let pkgs = pkgs
+                .as_object()
+                .ok_or_else(|| FlakeParseError::MalformedArch(arch.clone()))?;
 
-                    new_ps.insert(new_pkg.get_name(), new_pkg);
+            let mut new_ps: PkgMap = PkgMap::new();
+            for (attr_name, pkg_value) in pkgs.iter() {
+                if legacy && pkg_value["type"].as_str() != Some("derivation") {
+                    continue;
                 }
 
-                new_fp.insert(arch.clone(), new_ps);
+                let description: Option<String> = pkg_value["description"]
+                    .as_str()
+                    .filter(|val| !val.is_empty())
+                    .map(|val| val.into());
+
+                // Some flakes don't set a name, so the attribute key stands in for it
+                let full_name = pkg_value["name"].as_str().unwrap_or(attr_name);
+                // Prefer the version and pname attributes so names that don't embed a readable version still parse
+                let attr = |key: &str| pkg_value[key].as_str().filter(|val| !val.is_empty());
+                let new_pkg = match (attr("version"), attr("pname")) {
+                    (Some(version), _) => {
+                        Package::new_with_version(full_name, version, &description)
+                    }
+                    (None, Some(pname)) => Package::new_with_pname(full_name, pname, &description),
+                    (None, None) => match Package::new(full_name, &description) {
+                        // The attribute key usually is the pname, so use it to split names like foo-v1.1 that would otherwise not line up with other versions
+                        Package::Unparsable(_) => {
+                            Package::new_with_pname(full_name, attr_name, &description)
+                        }
+                        pkg => pkg,
+                    },
+                };
+
+                let new_pkg = match by_attr {
+                    true => new_pkg.with_name(attr_name),
+                    false => new_pkg,
+                };
+                new_ps.insert(new_pkg.get_name(), new_pkg);
             }
+
+            debug!(
+                "read {} packages for {}{}",
+                new_ps.len(),
+                arch,
+                if legacy { " (legacyPackages)" } else { "" }
+            );
+            new_fp.insert(arch.clone(), new_ps);
         }
 
-        Flake(new_fp)
+        Ok(new_fp)
+    }
+}
+
+/// Errors produced when flake json can't be read
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum FlakeParseError {
+    /// The json has no packages (or legacyPackages) output
+    MissingPackages,
+    /// The packages output for this arch is not an object
+    MalformedArch(String),
+    /// The json does not have the output that was asked for (ex. devShells)
+    MissingOutput(String),
+    /// The text is not valid json (holds the serde error)
+    InvalidJson(String),
+}
+
+impl fmt::Display for FlakeParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlakeParseError::MissingPackages => {
+                write!(f, "flake json has no packages or legacyPackages output")
+            }
+            FlakeParseError::MalformedArch(arch) => {
+                write!(f, "packages output for {} is not an attribute set", arch)
+            }
+            FlakeParseError::MissingOutput(output) => {
+                write!(f, "flake json has no {} output", output)
+            }
+            FlakeParseError::InvalidJson(err) => write!(f, "flake json is invalid: {}", err),
+        }
     }
 }
 
+impl error::Error for FlakeParseError {}
+
+/// Checks if a mapping onto new_name can't be used on an arch since the old packages already have new_name (and it isn't mapped away itself)
+fn mapping_taken(old_pkgs: &PkgMap, name_map: &HashMap<String, String>, new_name: &str) -> bool {
+    old_pkgs.contains_key(new_name) && !name_map.contains_key(new_name)
+}
+
+// --- TEMPLATE
+/// The template generate_report uses, see FlakeCompareData::template_context for the placeholders
+pub const FLAKE_TEMPLATE: &str = "## {{heading}}\n\
+    {{revision_line}}\
+    Report generated using [`nix-update-report`](https://github.com/aldenparker/nix-update-report.git).\n\
+    \n\
+    ### Stats\n\
+    {{stats}}\n\
+    ### Pkg Changes\n\
+    {{changes}}\
+    {{coverage_section}}\
+    {{footer}}";
+
 // --- FLAKE PKGS COMPARE
 /// FlakePkgs comparison data for a single architecture
-#[derive(PartialEq, Eq)]
+#[derive(PartialEq, Eq, Clone)]
 struct FlakeSingleArchCompareData {
     /// All packages that were added to the flake
     added: Vec<Package>,
-    /// All packages that were updated in the flake (Package is the new package and PkgCompareData holds the update info)
-    updated: Vec<(Package, PkgCompareData)>,
+    /// All packages that were updated in the flake as (old package, new package, update info)
+    updated: Vec<(Package, Package, PkgCompareData)>,
     /// All packages that were removed from the flake
     removed: Vec<Package>,
     /// The total packages in this arch
     total_pkgs: usize,
+    /// The packages in this arch whose name could not be parsed
+    unparsable_pkgs: usize,
+    /// The number of packages that did not change
+    unchanged: usize,
+    /// All packages that did not change (only kept when asked for)
+    unchanged_list: Option<Vec<Package>>,
+    /// Removed and added packages that look like renames as (old, new) (only found when asked for)
+    renamed: Option<Vec<(Package, Package)>>,
+}
+
+/// Options that change what FlakeCompareData keeps from the comparison
+#[derive(Default, Clone, Debug)]
+pub struct FlakeCompareOptions {
+    /// Keep the packages that did not change so they can be listed in the report
+    pub include_unchanged: bool,
+    /// Pair removed and added packages with the same version and similar names as renames
+    pub detect_renames: bool,
+    /// Only compare packages whose name the filter keeps
+    pub filter: NameFilter,
+    /// Old package names to compare as if they had another name in the new flake, as (old name, new name). Mappings onto a name the old flake already has are skipped (see Flake::taken_mappings).
+    pub name_map: HashMap<String, String>,
+    /// Look for packages in the new flake that are missing from some of its archs
+    pub check_coverage: bool,
+    /// Only flag packages on fewer than this many archs when checking coverage (every arch when none)
+    pub min_archs: Option<usize>,
+}
+
+/// Options that change how a FlakeCompareData report is rendered in markdown
+#[derive(Default, Clone, Debug)]
+pub struct FlakeReportOptions {
+    /// Render each package list as a table instead of a bulleted list
+    pub table: bool,
+    /// Show the descriptions of added and updated packages
+    pub show_descriptions: bool,
+    /// Show the old and new description of updated packages whose description changed
+    pub show_description_changes: bool,
+    /// Cut descriptions longer than this many characters short with an ellipsis (0 for no limit)
+    pub max_description_len: usize,
+    /// Only list this many entries of each added, updated, and removed list (the stats still count all of them)
+    pub limit: Option<usize>,
+    /// List each change once with the archs it happened on instead of listing every arch separately (descriptions are not shown)
+    pub by_package: bool,
+    /// List changes that are the same on every arch once under a Common section, leaving only arch specific changes under each arch
+    pub dedup_archs: bool,
+    /// The order the updated packages are listed in
+    pub update_order: UpdateOrder,
+    /// Start the report with a table of contents linking to each heading (left out of short reports and custom templates)
+    pub toc: bool,
+    /// Render the package changes as one table with a row per package and a column per arch instead of lists by arch
+    pub matrix: bool,
 }
 
 /// FlakePkgs comparison data for all packages in the flake
 #[derive(PartialEq, Eq)]
 pub struct FlakeCompareData {
-    /// All the package compare data by arch
-    pkg_data: HashMap<String, FlakeSingleArchCompareData>,
+    /// All the package compare data by arch (sorted so reports are deterministic)
+    pkg_data: BTreeMap<String, FlakeSingleArchCompareData>,
     /// Archs removed from this flake
     removed_archs: Vec<String>,
     /// Archs added to this flake
     added_archs: Vec<String>,
     /// The total number of archs this flake supports
     total_archs: usize,
+    /// A description of the filter packages went through, if any
+    filter: Option<String>,
+    /// Packages in the new flake missing from some of its archs as (name, archs missing it) (only found when asked for)
+    coverage: Option<Vec<(String, Vec<String>)>>,
+    /// The number of archs packages needed to be on to pass the coverage check, none for every arch
+    min_archs: Option<usize>,
+    /// The revisions the previous and next flakes resolved to, none if neither is known
+    revisions: Option<(String, String)>,
 }
 
 impl FlakeCompareData {
     pub fn new(old: &Flake, new: &Flake) -> FlakeCompareData {
+        FlakeCompareData::new_with_options(old, new, &FlakeCompareOptions::default())
+    }
+
+    pub fn new_with_options(
+        old: &Flake,
+        new: &Flake,
+        options: &FlakeCompareOptions,
+    ) -> FlakeCompareData {
+        // Filter while comparing so every count only covers matching packages without copying the flakes
+        let filter = &options.filter;
+
         let mut compare_data = FlakeCompareData {
-            pkg_data: HashMap::new(),
+            pkg_data: BTreeMap::new(),
             removed_archs: vec![],
             added_archs: vec![],
             total_archs: new.0.len(), // Only count the archs in new
+            filter: filter.describe(),
+            coverage: options.check_coverage.then(|| {
+                new.coverage_below_matching(options.min_archs.unwrap_or(new.0.len()), filter)
+            }),
+            min_archs: options.min_archs,
+            revisions: None,
         };
 
         // First go through archs
-        let comparable_archs: HashSet<String> = (old
-            .0
-            .keys()
-            .map(|val| val.clone())
-            .collect::<HashSet<String>>())
-        .intersection(
-            &new.0
-                .keys()
-                .map(|val| val.clone())
-                .collect::<HashSet<String>>(),
-        )
-        .map(|val| val.clone())
-        .collect();
-
         compare_data.added_archs = new
             .0
             .keys()
-            .filter(|&val| !comparable_archs.contains(val))
-            .map(|val| val.clone())
+            .filter(|&val| !old.0.contains_key(val))
+            .cloned()
             .collect();
+        compare_data.added_archs.sort();
 
         compare_data.removed_archs = old
             .0
             .keys()
-            .filter(|&val| !comparable_archs.contains(val))
-            .map(|val| val.clone())
+            .filter(|&val| !new.0.contains_key(val))
+            .cloned()
             .collect();
+        compare_data.removed_archs.sort();
 
-        // Create pkg compare values
-        for arch in comparable_archs.iter() {
-            let old_pkgs = old.0.get(arch).unwrap();
-            let new_pkgs = new.0.get(arch).unwrap();
+        // Create pkg compare values for the archs in both flakes
+        for (arch, old_pkgs) in old.0.iter() {
+            let Some(new_pkgs) = new.0.get(arch) else {
+                continue;
+            };
+            let kept_new_pkgs = || {
+                new_pkgs
+                    .iter()
+                    .filter(|(name, _)| filter.matches(name))
+                    .map(|(_, pkg)| pkg)
+            };
 
             let mut single_comp = FlakeSingleArchCompareData {
                 added: vec![],
                 updated: vec![],
                 removed: vec![],
-                total_pkgs: new_pkgs.len(), // only includes new packages since those are what is left
+                total_pkgs: kept_new_pkgs().count(), // only includes new packages since those are what is left
+                unparsable_pkgs: kept_new_pkgs()
+                    .filter(|pkg| matches!(pkg, Package::Unparsable(_)))
+                    .count(),
+                unchanged: 0,
+                unchanged_list: options.include_unchanged.then(Vec::new),
+                renamed: None,
             };
 
-            // Find updated and removed packages
-            for (name, old_pkg) in old_pkgs {
-                if let Some(new_pkg) = new_pkgs.get(name) {
-                    match PkgCompareData::new(old_pkg, new_pkg).unwrap() {
-                        PkgCompareData::Unchanged => (),
-                        val => single_comp.updated.push((new_pkg.clone(), val)),
+            // Find updated and removed packages, looking mapped packages up by their new name
+            let mapped_name = |name: &String| {
+                options
+                    .name_map
+                    .get(name)
+                    .filter(|new_name| !mapping_taken(old_pkgs, &options.name_map, new_name))
+            };
+            for (name, old_pkg) in old_pkgs.iter().filter(|(name, _)| filter.matches(name)) {
+                let new_name = mapped_name(name).unwrap_or(name);
+                if let Some(new_pkg) = new_pkgs.get(new_name) {
+                    let compare_data = match new_name == name {
+                        true => PkgCompareData::new(old_pkg, new_pkg),
+                        false => PkgCompareData::new(&old_pkg.with_name(new_name), new_pkg),
+                    };
+                    match compare_data.unwrap() {
+                        PkgCompareData::Unchanged => {
+                            single_comp.unchanged += 1;
+                            if let Some(unchanged) = single_comp.unchanged_list.as_mut() {
+                                unchanged.push(new_pkg.clone());
+                            }
+                        }
+                        val => single_comp
+                            .updated
+                            .push((old_pkg.clone(), new_pkg.clone(), val)),
                     }
                 } else {
                     single_comp.removed.push(old_pkg.clone());
                 }
             }
 
-            // Find new packages
-            for (name, pkg) in new_pkgs {
-                if !old_pkgs.contains_key(name) {
+            // Find new packages, skipping the ones an old package maps to
+            let mapped_names: HashSet<&String> = old_pkgs
+                .keys()
+                .filter(|old_name| filter.matches(old_name))
+                .filter_map(mapped_name)
+                .collect();
+            // A name that was mapped away is new even if the old flake had it, since the old package was compared under its new name
+            for (name, pkg) in new_pkgs.iter().filter(|(name, _)| filter.matches(name)) {
+                let compared = old_pkgs.contains_key(name) && mapped_name(name).is_none();
+                if !compared && !mapped_names.contains(name) {
                     single_comp.added.push(pkg.clone());
                 }
             }
 
+            // Keep package lists in a stable order (names are borrowed so sorting doesn't allocate)
+            single_comp.added.sort_by(|a, b| a.name().cmp(b.name()));
+            single_comp
+                .updated
+                .sort_by(|(_, a, _), (_, b, _)| a.name().cmp(b.name()));
+            single_comp.removed.sort_by(|a, b| a.name().cmp(b.name()));
+            if let Some(unchanged) = single_comp.unchanged_list.as_mut() {
+                unchanged.sort_by(|a, b| a.name().cmp(b.name()));
+            }
+
+            // Renames can only be trusted when the version stayed the same
+            if options.detect_renames {
+                single_comp.renamed = Some(pair_renames(
+                    &mut single_comp.removed,
+                    &mut single_comp.added,
+                    |pkg| pkg.name(),
+                    |old_pkg, new_pkg| match (old_pkg, new_pkg) {
+                        (
+                            Package::Parsed { version, .. },
+                            Package::Parsed {
+                                version: new_version,
+                                ..
+                            },
+                        ) => version == new_version,
+                        _ => false,
+                    },
+                ));
+            }
+
             compare_data.pkg_data.insert(arch.clone(), single_comp);
         }
 
@@ -164,8 +601,638 @@ impl FlakeCompareData {
         self.pkg_data.values().map(|val| val.total_pkgs).sum()
     }
 
+    /// Grab the number of added packages across all archs
+    fn added_pkgs(&self) -> usize {
+        self.pkg_data.values().map(|val| val.added.len()).sum()
+    }
+
+    /// Grab the number of updated packages across all archs
+    fn updated_pkgs(&self) -> usize {
+        self.pkg_data.values().map(|val| val.updated.len()).sum()
+    }
+
+    /// Grab the number of removed packages across all archs
+    fn removed_pkgs(&self) -> usize {
+        self.pkg_data.values().map(|val| val.removed.len()).sum()
+    }
+
+    /// A note saying the counts only cover filtered packages, empty if there is no filter
+    fn filter_note(&self) -> String {
+        self.filter.as_ref().map_or("".into(), |val| {
+            format!("Only counting packages {}\n\n", val)
+        })
+    }
+
+    /// Sets the revisions the previous and next flakes resolved to so the report shows exactly what was compared. Unknown revisions show as unknown.
+    pub fn set_revisions(&mut self, previous: Option<String>, next: Option<String>) {
+        self.revisions = match (previous, next) {
+            (None, None) => None,
+            (previous, next) => Some((
+                previous.unwrap_or("unknown".into()),
+                next.unwrap_or("unknown".into()),
+            )),
+        };
+    }
+
+    /// Render a coverage gap as `name: missing on arch, arch` (or `name: on every arch` when there are too few archs to pass)
+    fn coverage_text((name, missing): &(String, Vec<String>)) -> String {
+        match missing.is_empty() {
+            true => format!("{}: on every arch", name),
+            false => format!("{}: missing on {}", name, missing.join(", ")),
+        }
+    }
+
+    /// Describes which packages the coverage check flags
+    fn coverage_note(&self) -> String {
+        match self.min_archs {
+            Some(min_archs) => format!(
+                "Packages on fewer than {} archs of the new flake",
+                min_archs
+            ),
+            None => "Packages missing from some archs of the new flake".into(),
+        }
+    }
+
+    /// Checks if any packages or archs were removed
+    pub fn has_removals(&self) -> bool {
+        !self.removed_archs.is_empty()
+            || self.pkg_data.values().any(|data| !data.removed.is_empty())
+    }
+
+    /// Describes every removed arch and package, one line each (ex. `foo removed on x86_64-linux`)
+    pub fn removal_lines(&self) -> Vec<String> {
+        self.removed_archs
+            .iter()
+            .map(|arch| format!("arch {} removed", arch))
+            .chain(self.pkg_data.iter().flat_map(|(arch, data)| {
+                data.removed
+                    .iter()
+                    .map(move |pkg| format!("{} removed on {}", pkg.name(), arch))
+            }))
+            .collect()
+    }
+
+    /// Describes every downgraded package, one line each (ex. `foo: 1.2 -> 1.1, downgrade on x86_64-linux`)
+    pub fn downgrade_lines(&self) -> Vec<String> {
+        self.pkg_data
+            .iter()
+            .flat_map(|(arch, data)| {
+                data.updated
+                    .iter()
+                    .filter(|(_, _, compare_data)| {
+                        matches!(
+                            compare_data,
+                            PkgCompareData::Changed {
+                                direction: Some(VersionDirection::Downgrade),
+                                ..
+                            }
+                        )
+                    })
+                    .map(move |(_, _, compare_data)| {
+                        format!("{} on {}", Self::change_text(compare_data), arch)
+                    })
+            })
+            .collect()
+    }
+
+    /// Checks if any package versions went backwards
+    pub fn has_downgrades(&self) -> bool {
+        self.pkg_data.values().any(|data| {
+            data.updated.iter().any(|(_, _, compare_data)| {
+                matches!(
+                    compare_data,
+                    PkgCompareData::Changed {
+                        direction: Some(VersionDirection::Downgrade),
+                        ..
+                    }
+                )
+            })
+        })
+    }
+
+    /// Count the updates at a bump level across all archs
+    fn bump_count(&self, level: BumpLevel) -> usize {
+        self.pkg_data
+            .values()
+            .flat_map(|data| data.updated.iter())
+            .filter(|(_, _, compare_data)| {
+                matches!(compare_data, PkgCompareData::Changed { bump: Some(val), .. } if *val == level)
+            })
+            .count()
+    }
+
+    /// Render a single package as `name: version`
+    fn pkg_text(pkg: &Package) -> String {
+        match pkg {
+            Package::Unparsable(name) => format!("{}: unparsable", name),
+            Package::Parsed {
+                name,
+                version,
+                description: _,
+            } => format!("{}: {}", name, version),
+        }
+    }
+
+    /// Render a single package as a list line
+    fn pkg_line(pkg: &Package) -> String {
+        format!(" - {}\n", Self::pkg_text(pkg))
+    }
+
+    /// Grab the version of a package as a string
+    fn pkg_version(pkg: &Package) -> String {
+        match pkg {
+            Package::Unparsable(_) => "unparsable".into(),
+            Package::Parsed { version, .. } => version.to_string(),
+        }
+    }
+
+    /// Splits an update into its old version, new version, and a note on the kind of change (ex. minor, downgrade)
+    fn split_change(compare_data: &PkgCompareData) -> (String, String, String) {
+        let PkgCompareData::Changed {
+            change_string,
+            direction,
+            bump,
+            description_change,
+            ..
+        } = compare_data
+        else {
+            unreachable!()
+        };
+
+        // The change string looks like `name: old -> new, notes`
+        let versions = change_string
+            .split_once(": ")
+            .map_or(change_string.as_str(), |(_, val)| val);
+        let (old, rest) = versions.split_once(" -> ").unwrap_or((versions, ""));
+        let new = rest.split_once(", ").map_or(rest, |(val, _)| val);
+
+        let notes: Vec<&str> = [
+            bump.map(|val| match val {
+                BumpLevel::Major => "major",
+                BumpLevel::Minor => "minor",
+                BumpLevel::Patch => "patch",
+                BumpLevel::Other => "other",
+                BumpLevel::Unknown => "unknown",
+            }),
+            (*direction == Some(VersionDirection::Downgrade)).then_some("downgrade"),
+            (*description_change == Some(true)).then_some("description changed"),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        (old.into(), new.into(), notes.join(", "))
+    }
+
+    /// Grab the description to show for a package, cut down to max_len characters (0 for no limit). When the old package is given and the description changed this shows `old -> new`.
+    fn description_text(old: Option<&Package>, new: &Package, max_len: usize) -> Option<String> {
+        let describe = |pkg: &Package| match pkg {
+            Package::Parsed {
+                description: Some(description),
+                ..
+            } => Some(match max_len > 0 && description.chars().count() > max_len {
+                true => format!(
+                    "{}…",
+                    description
+                        .chars()
+                        .take(max_len.saturating_sub(1))
+                        .collect::<String>()
+                        .trim_end()
+                ),
+                false => description.clone(),
+            }),
+            _ => None,
+        };
+
+        match old {
+            Some(old) if Self::description_changed(old, new) => Some(format!(
+                "{} -> {}",
+                describe(old).unwrap_or("none".into()),
+                describe(new).unwrap_or("none".into())
+            )),
+            _ => describe(new),
+        }
+    }
+
+    /// Checks if the description of a package changed
+    fn description_changed(old: &Package, new: &Package) -> bool {
+        match (old, new) {
+            (
+                Package::Parsed { description, .. },
+                Package::Parsed {
+                    description: new_description,
+                    ..
+                },
+            ) => description != new_description,
+            _ => false,
+        }
+    }
+
+    /// Render rows as a markdown table (cells holding | are escaped)
+    fn table(header: &[&str], rows: &[Vec<String>]) -> String {
+        if rows.is_empty() {
+            return "None\n".into();
+        }
+
+        let row = |cells: Vec<String>| format!("| {} |\n", cells.join(" | "));
+        let mut table = row(header.iter().map(|val| val.to_string()).collect());
+        table.push_str(&row(header.iter().map(|_| "---".to_string()).collect()));
+        for cells in rows {
+            table.push_str(&row(cells
+                .iter()
+                .map(|val| val.replace('|', "\\|"))
+                .collect()));
+        }
+
+        table
+    }
+
+    /// Grab the change string of an updated package
+    fn change_text(compare_data: &PkgCompareData) -> &str {
+        match compare_data {
+            PkgCompareData::Changed { change_string, .. } => change_string,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Where an updated package sorts when ordering by bump. Changes that only touched the description come last.
+    fn bump_rank(compare_data: &PkgCompareData) -> (bool, Option<BumpLevel>) {
+        match compare_data {
+            PkgCompareData::Changed { bump, .. } => (bump.is_none(), *bump),
+            PkgCompareData::Unchanged => (true, None),
+        }
+    }
+
+    /// The bump_rank of every update by its change text, for sorting updates that were grouped across archs
+    fn bump_ranks(&self) -> HashMap<&str, (bool, Option<BumpLevel>)> {
+        self.pkg_data
+            .values()
+            .flat_map(|data| data.updated.iter())
+            .map(|(_, _, compare_data)| {
+                (
+                    Self::change_text(compare_data),
+                    Self::bump_rank(compare_data),
+                )
+            })
+            .collect()
+    }
+
+    /// Borrows an arch's updated packages in the order they should be listed
+    fn ordered_updates(
+        updated: &[(Package, Package, PkgCompareData)],
+        order: UpdateOrder,
+    ) -> Vec<&(Package, Package, PkgCompareData)> {
+        let mut updates: Vec<&(Package, Package, PkgCompareData)> = updated.iter().collect();
+        // The list is already sorted by name and the sort is stable, so names stay sorted within each bump
+        if order == UpdateOrder::Bump {
+            updates.sort_by_key(|(_, _, compare_data)| Self::bump_rank(compare_data));
+        }
+        updates
+    }
+
+    /// The sections the package lists are rendered in as (heading, changes). With dedup the changes shared by every arch come first under Common and are left out of each arch.
+    fn report_sections(&self, dedup: bool) -> Vec<(&str, Cow<'_, FlakeSingleArchCompareData>)> {
+        if !dedup || self.pkg_data.len() < 2 {
+            return self
+                .pkg_data
+                .iter()
+                .map(|(arch, data)| (arch.as_str(), Cow::Borrowed(data)))
+                .collect();
+        }
+
+        // Entries are matched by how they render so only identical lines are shared
+        let in_all = |entries: &dyn Fn(&FlakeSingleArchCompareData) -> Vec<String>| {
+            self.pkg_data
+                .values()
+                .map(|data| entries(data).into_iter().collect::<HashSet<String>>())
+                .reduce(|acc, e| acc.intersection(&e).cloned().collect())
+                .unwrap_or_default()
+        };
+        let common_added = in_all(&|data| data.added.iter().map(Self::pkg_text).collect());
+        let common_updated = in_all(&|data| {
+            data.updated
+                .iter()
+                .map(|(_, _, compare_data)| Self::change_text(compare_data).into())
+                .collect()
+        });
+        let common_removed = in_all(&|data| data.removed.iter().map(Self::pkg_text).collect());
+
+        // Keeps either the shared or the arch specific changes of an arch
+        let split = |data: &FlakeSingleArchCompareData, shared: bool| {
+            let mut split_data = data.clone();
+            split_data
+                .added
+                .retain(|pkg| common_added.contains(&Self::pkg_text(pkg)) == shared);
+            split_data.updated.retain(|(_, _, compare_data)| {
+                common_updated.contains(Self::change_text(compare_data)) == shared
+            });
+            split_data
+                .removed
+                .retain(|pkg| common_removed.contains(&Self::pkg_text(pkg)) == shared);
+            if shared {
+                split_data.renamed = None;
+                split_data.unchanged_list = None;
+            }
+            split_data
+        };
+
+        let first = self.pkg_data.values().next().unwrap();
+        std::iter::once(("Common", Cow::Owned(split(first, true))))
+            .chain(
+                self.pkg_data
+                    .iter()
+                    .map(|(arch, data)| (arch.as_str(), Cow::Owned(split(data, false)))),
+            )
+            .collect()
+    }
+
+    /// Collects the same entry from every arch into one, as (entry, archs it shows up on) sorted by entry
+    fn group_archs<'a>(
+        entries: impl Iterator<Item = (&'a str, String)>,
+    ) -> Vec<(String, Vec<&'a str>)> {
+        let mut grouped: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+        for (arch, entry) in entries {
+            grouped.entry(entry).or_default().push(arch);
+        }
+
+        grouped.into_iter().collect()
+    }
+
+    /// Render the package lists keyed by package instead of by arch, as [changes, added, updated, removed] like template_context uses
+    fn by_package_lists(&self, options: &FlakeReportOptions) -> [String; 4] {
+        let render = |entries: Vec<(String, Vec<&str>)>, header: &[&str]| {
+            let (kept, hidden) = limit_list(&entries, options.limit);
+            let mut list = match options.table {
+                true => Self::table(
+                    header,
+                    &kept
+                        .iter()
+                        .map(|(entry, archs)| {
+                            // Entries look like `name: rest`
+                            let (name, rest) = entry.split_once(": ").unwrap_or((entry, ""));
+                            vec![name.into(), rest.into(), archs.join(", ")]
+                        })
+                        .collect::<Vec<Vec<String>>>(),
+                ),
+                false => kept
+                    .iter()
+                    .map(|(entry, archs)| format!(" - {} ({})\n", entry, archs.join(", ")))
+                    .reduce(|mut acc, e| {
+                        acc.push_str(e.as_str());
+                        acc
+                    })
+                    .unwrap_or("None\n".into()),
+            };
+
+            if hidden > 0 {
+                // Tables need a blank line after them or the note becomes a row
+                if options.table {
+                    list.push('\n');
+                }
+                list.push_str(&format!("...and {} more\n", hidden));
+            }
+            list
+        };
+        let each_arch = |pick: &dyn Fn(&FlakeSingleArchCompareData) -> Vec<String>| {
+            Self::group_archs(self.pkg_data.iter().flat_map(|(arch, data)| {
+                pick(data)
+                    .into_iter()
+                    .map(move |entry| (arch.as_str(), entry))
+            }))
+        };
+
+        let added = render(
+            each_arch(&|data| data.added.iter().map(Self::pkg_text).collect()),
+            &["Package", "Version", "Archs"],
+        );
+        let mut updated_entries = each_arch(&|data| {
+            data.updated
+                .iter()
+                .map(|(_, _, compare_data)| Self::change_text(compare_data).into())
+                .collect()
+        });
+        if options.update_order == UpdateOrder::Bump {
+            let ranks = self.bump_ranks();
+            updated_entries.sort_by_key(|(entry, _)| ranks.get(entry.as_str()).copied());
+        }
+        let updated = render(updated_entries, &["Package", "Change", "Archs"]);
+        let removed = render(
+            each_arch(&|data| data.removed.iter().map(Self::pkg_text).collect()),
+            &["Package", "Version", "Archs"],
+        );
+
+        // Only list renames if they were looked for
+        let renamed = match self.pkg_data.values().any(|data| data.renamed.is_some()) {
+            true => format!(
+                "#### Renamed\n\
+                {}\n",
+                render(
+                    each_arch(&|data| {
+                        data.renamed
+                            .iter()
+                            .flatten()
+                            .map(|(old_pkg, new_pkg)| {
+                                format!("{}: {}", old_pkg.get_name(), Self::pkg_text(new_pkg))
+                            })
+                            .collect()
+                    }),
+                    &["Old", "New", "Archs"],
+                )
+            ),
+            false => "".into(),
+        };
+
+        let changes = format!(
+            "#### Added\n\
+            {}\n\
+            #### Updated\n\
+            {}\n\
+            #### Removed\n\
+            {}\n\
+            {}",
+            added, updated, removed, renamed
+        );
+        [changes, added, updated, removed]
+    }
+
+    /// Render every package the comparison kept as a table with a row per package and a column per arch. A cell holds the new version marked + if it was added or ~ if it was updated, the old version marked - if it was removed, the version alone if it did not change (only known when unchanged packages were kept), and nothing if the package is not on that arch.
+    fn matrix(&self, options: &FlakeReportOptions) -> String {
+        let archs: Vec<&str> = self.pkg_data.keys().map(|val| val.as_str()).collect();
+        let mut cells: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for (i, data) in self.pkg_data.values().enumerate() {
+            let mut set = |pkg: &Package, mark: &str| {
+                cells
+                    .entry(pkg.get_name())
+                    .or_insert_with(|| vec!["".into(); archs.len()])[i] =
+                    format!("{}{}", mark, Self::pkg_version(pkg));
+            };
+
+            for pkg in data.unchanged_list.iter().flatten() {
+                set(pkg, "");
+            }
+            for pkg in data.added.iter() {
+                set(pkg, "+ ");
+            }
+            for (_, pkg, _) in data.updated.iter() {
+                set(pkg, "~ ");
+            }
+            for pkg in data.removed.iter() {
+                set(pkg, "- ");
+            }
+            // A rename is the old name going away and the new one showing up
+            for (old_pkg, new_pkg) in data.renamed.iter().flatten() {
+                set(old_pkg, "- ");
+                set(new_pkg, "+ ");
+            }
+        }
+
+        let rows: Vec<Vec<String>> = cells
+            .into_iter()
+            .map(|(name, row)| std::iter::once(name).chain(row).collect())
+            .collect();
+        let (kept, hidden) = limit_list(&rows, options.limit);
+        let header: Vec<&str> = std::iter::once("Package").chain(archs).collect();
+
+        let mut matrix = Self::table(&header, kept);
+        if hidden > 0 {
+            // Tables need a blank line after them or the note becomes a row
+            matrix.push_str(&format!("\n...and {} more\n", hidden));
+        }
+        if !rows.is_empty() {
+            matrix.push_str("\n`+` added, `~` updated, `-` removed, empty if not on the arch\n");
+        }
+        matrix
+    }
+
+    /// Generate a one line summary of the comparison (ex. `+3 ~10 -1 across 2 archs`)
+    pub fn stat_line(&self) -> String {
+        format!(
+            "+{} ~{} -{} across {} archs",
+            self.added_pkgs(),
+            self.updated_pkgs(),
+            self.removed_pkgs(),
+            self.total_archs
+        )
+    }
+
+    /// Generate the changes as a Keep a Changelog release (see template::changelog). Entries only on some archs list them.
+    pub fn generate_changelog(&self, version: &Option<String>) -> String {
+        let each_arch = |pick: &dyn Fn(&FlakeSingleArchCompareData) -> Vec<String>| {
+            Self::group_archs(self.pkg_data.iter().flat_map(|(arch, data)| {
+                pick(data)
+                    .into_iter()
+                    .map(move |entry| (arch.as_str(), entry))
+            }))
+            .into_iter()
+            .map(|(entry, archs)| match archs.len() == self.pkg_data.len() {
+                true => entry,
+                false => format!("{} ({})", entry, archs.join(", ")),
+            })
+            .collect::<Vec<String>>()
+        };
+
+        let mut added: Vec<String> = self
+            .added_archs
+            .iter()
+            .map(|arch| format!("arch {}", arch))
+            .collect();
+        added.extend(each_arch(&|data| {
+            data.added.iter().map(Self::pkg_text).collect()
+        }));
+        let changed = each_arch(&|data| {
+            data.updated
+                .iter()
+                .map(|(_, _, compare_data)| Self::change_text(compare_data).into())
+                .chain(data.renamed.iter().flatten().map(|(old_pkg, new_pkg)| {
+                    format!("{} renamed to {}", old_pkg.name(), new_pkg.name())
+                }))
+                .collect()
+        });
+        let mut removed: Vec<String> = self
+            .removed_archs
+            .iter()
+            .map(|arch| format!("arch {}", arch))
+            .collect();
+        removed.extend(each_arch(&|data| {
+            data.removed.iter().map(Self::pkg_text).collect()
+        }));
+
+        template::changelog(version, &added, &changed, &removed)
+    }
+
+    /// Generate a short summary as a Slack or Discord webhook payload. Removals are listed first, then updates with the biggest bumps first, then additions, each once with the archs it happened on unless that was all of them, and they are cut short to fit the message limit.
+    pub fn generate_webhook(&self, title: &Option<String>, style: WebhookStyle) -> String {
+        let each_arch = |pick: &dyn Fn(&FlakeSingleArchCompareData) -> Vec<String>| {
+            Self::group_archs(self.pkg_data.iter().flat_map(|(arch, data)| {
+                pick(data)
+                    .into_iter()
+                    .map(move |entry| (arch.as_str(), entry))
+            }))
+        };
+        let line = |mark: &str, (entry, archs): (String, Vec<&str>)| match archs.len()
+            == self.pkg_data.len()
+        {
+            true => format!("{} {}", mark, entry),
+            false => format!("{} {} ({})", mark, entry, archs.join(", ")),
+        };
+
+        let mut updated = each_arch(&|data| {
+            data.updated
+                .iter()
+                .map(|(_, _, compare_data)| Self::change_text(compare_data).into())
+                .collect()
+        });
+        let ranks = self.bump_ranks();
+        updated.sort_by_key(|(entry, _)| ranks.get(entry.as_str()).copied());
+
+        let changes: Vec<String> =
+            each_arch(&|data| data.removed.iter().map(Self::pkg_text).collect())
+                .into_iter()
+                .map(|val| line("-", val))
+                .chain(updated.into_iter().map(|val| line("~", val)))
+                .chain(
+                    each_arch(&|data| data.added.iter().map(Self::pkg_text).collect())
+                        .into_iter()
+                        .map(|val| line("+", val)),
+                )
+                .collect();
+
+        webhook::payload(
+            style,
+            &format!(
+                "nix-update-report{}",
+                title.clone().map_or("".into(), |val| format!(" - {}", val))
+            ),
+            &self.stat_line(),
+            &changes,
+        )
+    }
+
     /// Generate comparison report in markdown
     pub fn generate_report(&self, title: &Option<String>) -> String {
+        self.generate_report_with_options(title, &FlakeReportOptions::default())
+    }
+
+    /// Generate comparison report in markdown using the options given
+    pub fn generate_report_with_options(
+        &self,
+        title: &Option<String>,
+        options: &FlakeReportOptions,
+    ) -> String {
+        let report = template::render(FLAKE_TEMPLATE, &self.template_context(title, options));
+        match options.toc {
+            true => template::add_toc(&report),
+            false => report,
+        }
+    }
+
+    /// Collect the rendered pieces of the report a template can use. Holds `heading`, `title`, `stats`, `changes` (every arch's lists), `added`, `updated`, and `removed` (one category's lists by arch), the `added_count`, `updated_count`, `removed_count`, and `archs` numbers, `coverage_section` (empty unless coverage was checked), `revision_line` (empty unless the revisions are known), and `version`, `generated_at`, and `footer` for when and with what the report was made.
+    pub fn template_context(
+        &self,
+        title: &Option<String>,
+        options: &FlakeReportOptions,
+    ) -> TemplateContext {
+        let generated_at = template::generated_at();
         let by_arch_stats = self
             .pkg_data
             .iter()
@@ -175,6 +1242,9 @@ impl FlakeCompareData {
                     Added: {}\n\
                     Updated: {}\n\
                     Removed: {}\n\
+                    {}\
+                    Unchanged: {}\n\
+                    Unparsable: {}\n\
                     Total: {}\n\
                     \n\
                     ",
@@ -182,6 +1252,11 @@ impl FlakeCompareData {
                     data.added.len(),
                     data.updated.len(),
                     data.removed.len(),
+                    data.renamed
+                        .as_ref()
+                        .map_or("".into(), |val| format!("Renamed: {}\n", val.len())),
+                    data.unchanged,
+                    data.unparsable_pkgs,
                     data.total_pkgs
                 )
             })
@@ -191,98 +1266,231 @@ impl FlakeCompareData {
             })
             .unwrap_or("".into());
 
-        let mut report = format!(
-            "## nix-update-report{}\n\
-            Report generated using [`nix-update-report`](https://github.com/aldenparker/nix-update-report.git).\n\
-            \n\
-            ### Stats\n\
+        let stats = format!(
+            "{}\
             #### By Arch\n\
             {}\
             #### Totals\n\
             Added Pkgs: {}\n\
-            Updated Pkgs: {}\n\
+            Updated Pkgs: {} ({} of total)\n\
+            Bumps: Major: {}, Minor: {}, Patch: {}, Other: {}, Unknown: {}\n\
             Removed Pkgs: {}\n\
+            {}\
+            Unchanged Pkgs: {}\n\
+            Unparsable Pkgs: {}\n\
             Pkgs: {}\n\
+            Net Pkgs: {}\n\
             Added Archs: {}\n\
             Removed Archs: {}\n\
             Archs: {}\n\
-            \n\
+            Net Archs: {}\n\
             ",
-            title.clone().map_or("".into(), |val| format!(" - {}", val)),
+            self.filter_note(),
             by_arch_stats,
+            self.added_pkgs(),
+            self.updated_pkgs(),
+            percent(self.updated_pkgs(), self.total_pkgs()),
+            self.bump_count(BumpLevel::Major),
+            self.bump_count(BumpLevel::Minor),
+            self.bump_count(BumpLevel::Patch),
+            self.bump_count(BumpLevel::Other),
+            self.bump_count(BumpLevel::Unknown),
+            self.removed_pkgs(),
             self.pkg_data
-                .iter()
-                .map(|(_, data)| data.added.len())
-                .sum::<usize>(),
+                .values()
+                .filter_map(|data| data.renamed.as_ref().map(|val| val.len()))
+                .reduce(|acc, e| acc + e)
+                .map_or("".into(), |val| format!("Renamed Pkgs: {}\n", val)),
             self.pkg_data
-                .iter()
-                .map(|(_, data)| data.updated.len())
+                .values()
+                .map(|data| data.unchanged)
                 .sum::<usize>(),
             self.pkg_data
-                .iter()
-                .map(|(_, data)| data.removed.len())
+                .values()
+                .map(|data| data.unparsable_pkgs)
                 .sum::<usize>(),
             self.total_pkgs(),
+            net_change(self.added_pkgs(), self.removed_pkgs()),
             self.added_archs.len(),
             self.removed_archs.len(),
-            self.total_archs
+            self.total_archs,
+            net_change(self.added_archs.len(), self.removed_archs.len())
         );
 
         // Generate lists
-        let pkgs_by_arch = (&self.pkg_data)
-            .iter()
+        let pkgs_by_arch = self
+            .report_sections(options.dedup_archs)
+            .into_iter()
             .map(|(arch, pkgs)| {
                 // Grab correct strings for each category
-                let added = (&pkgs.added)
-                    .iter()
-                    .map(|pkg| match pkg {
-                        Package::Unparsable(name) => format!(" - {}: unparsable\n", name),
-                        Package::Parsed {
-                            name,
-                            version,
-                            description: _,
-                        } => format!(" - {}: {}\n", name, version.to_string()),
-                    })
-                    .reduce(|mut acc, e| {
-                        acc.push_str(e.as_str());
-                        acc
-                    })
-                    .unwrap_or("None\n".into());
+                let list = |lines: Vec<String>| {
+                    lines
+                        .into_iter()
+                        .reduce(|mut acc, e| {
+                            acc.push_str(e.as_str());
+                            acc
+                        })
+                        .unwrap_or("None\n".into())
+                };
+                let pkg_rows = |list: &[Package]| -> Vec<Vec<String>> {
+                    list.iter()
+                        .map(|pkg| vec![pkg.get_name(), Self::pkg_version(pkg)])
+                        .collect()
+                };
 
-                let updated = (&pkgs.updated)
-                    .iter()
-                    .map(|(_, compare_data)| match compare_data {
-                        PkgCompareData::Changed {
-                            change_string,
-                            version_change: _,
-                            description_change: _,
-                        } => format!("{}\n", change_string),
-                        _ => unreachable!(),
-                    })
-                    .reduce(|mut acc, e| {
-                        acc.push_str(e.as_str());
-                        acc
+                // Descriptions are only shown for added and updated packages
+                let description = |old: Option<&Package>, new: &Package| {
+                    let changed = old.is_some_and(|old| Self::description_changed(old, new));
+                    Self::description_text(old, new, options.max_description_len).filter(|_| {
+                        options.show_descriptions || (options.show_description_changes && changed)
                     })
-                    .unwrap_or("None\n".into());
+                };
+                let with_description = |mut header: Vec<&'static str>| {
+                    if options.show_descriptions || options.show_description_changes {
+                        header.push("Description");
+                    }
+                    header
+                };
+                let with_description_cell = |mut row: Vec<String>, text: Option<String>| {
+                    if options.show_descriptions || options.show_description_changes {
+                        row.push(text.unwrap_or_default());
+                    }
+                    row
+                };
+                let description_suffix =
+                    |text: Option<String>| text.map_or("".into(), |val| format!(" ({})", val));
 
-                let removed = (&pkgs.removed)
-                    .iter()
-                    .map(|pkg| match pkg {
-                        Package::Unparsable(name) => format!(" - {}: unparsable\n", name),
-                        Package::Parsed {
-                            name,
-                            version,
-                            description: _,
-                        } => format!(" - {}: {}\n", name, version.to_string()),
-                    })
-                    .reduce(|mut acc, e| {
-                        acc.push_str(e.as_str());
-                        acc
-                    })
-                    .unwrap_or("None\n".into());
+                // Only the first entries are listed if there is a limit
+                let (added_pkgs, added_hidden) = limit_list(&pkgs.added, options.limit);
+                let updated_order = Self::ordered_updates(&pkgs.updated, options.update_order);
+                let (updated_pkgs, updated_hidden) = limit_list(&updated_order, options.limit);
+                let (removed_pkgs, removed_hidden) = limit_list(&pkgs.removed, options.limit);
+                let more = |mut list: String, hidden: usize| {
+                    if hidden > 0 {
+                        // Tables need a blank line after them or the note becomes a row
+                        if options.table {
+                            list.push('\n');
+                        }
+                        list.push_str(&format!("...and {} more\n", hidden));
+                    }
+                    list
+                };
+
+                let (added, updated, removed) = match options.table {
+                    true => (
+                        Self::table(
+                            &with_description(vec!["Package", "Version"]),
+                            &added_pkgs
+                                .iter()
+                                .map(|pkg| {
+                                    with_description_cell(
+                                        vec![pkg.get_name(), Self::pkg_version(pkg)],
+                                        description(None, pkg),
+                                    )
+                                })
+                                .collect::<Vec<Vec<String>>>(),
+                        ),
+                        Self::table(
+                            &with_description(vec!["Package", "Old", "New", "Change"]),
+                            &updated_pkgs
+                                .iter()
+                                .map(|(old_pkg, pkg, compare_data)| {
+                                    let (old, new, notes) = Self::split_change(compare_data);
+                                    with_description_cell(
+                                        vec![pkg.get_name(), old, new, notes],
+                                        description(Some(old_pkg), pkg),
+                                    )
+                                })
+                                .collect::<Vec<Vec<String>>>(),
+                        ),
+                        Self::table(&["Package", "Version"], &pkg_rows(removed_pkgs)),
+                    ),
+                    false => (
+                        list(
+                            added_pkgs
+                                .iter()
+                                .map(|pkg| {
+                                    format!(
+                                        " - {}{}\n",
+                                        Self::pkg_text(pkg),
+                                        description_suffix(description(None, pkg))
+                                    )
+                                })
+                                .collect(),
+                        ),
+                        list(
+                            updated_pkgs
+                                .iter()
+                                .map(|(old_pkg, pkg, compare_data)| {
+                                    format!(
+                                        "{}{}\n",
+                                        Self::change_text(compare_data),
+                                        description_suffix(description(Some(old_pkg), pkg))
+                                    )
+                                })
+                                .collect(),
+                        ),
+                        list(removed_pkgs.iter().map(Self::pkg_line).collect()),
+                    ),
+                };
+
+                let (added, updated, removed) = (
+                    more(added, added_hidden),
+                    more(updated, updated_hidden),
+                    more(removed, removed_hidden),
+                );
+
+                // Only list renames if they were looked for
+                let renamed = pkgs.renamed.as_ref().map_or("".into(), |renamed| {
+                    format!(
+                        "##### Renamed\n\
+                        {}\n\
+                        ",
+                        match options.table {
+                            true => Self::table(
+                                &["Old", "New", "Version"],
+                                &renamed
+                                    .iter()
+                                    .map(|(old_pkg, new_pkg)| {
+                                        vec![
+                                            old_pkg.get_name(),
+                                            new_pkg.get_name(),
+                                            Self::pkg_version(new_pkg),
+                                        ]
+                                    })
+                                    .collect::<Vec<Vec<String>>>(),
+                            ),
+                            false => list(
+                                renamed
+                                    .iter()
+                                    .map(|(old_pkg, new_pkg)| {
+                                        format!(
+                                            " - {} -> {}\n",
+                                            old_pkg.get_name(),
+                                            Self::pkg_text(new_pkg)
+                                        )
+                                    })
+                                    .collect(),
+                            ),
+                        }
+                    )
+                });
+
+                // Only list unchanged packages if they were kept
+                let unchanged = pkgs.unchanged_list.as_ref().map_or("".into(), |unchanged| {
+                    format!(
+                        "##### Unchanged\n\
+                        {}\n\
+                        ",
+                        match options.table {
+                            true => Self::table(&["Package", "Version"], &pkg_rows(unchanged)),
+                            false => list(unchanged.iter().map(Self::pkg_line).collect()),
+                        }
+                    )
+                });
 
                 // Create arch section
-                format!(
+                let changes = format!(
                     "#### {}\n\
                     ##### Added\n\
                     {}\n\
@@ -290,18 +1498,319 @@ impl FlakeCompareData {
                     {}\n\
                     ##### Removed\n\
                     {}\n\
+                    {}\
+                    {}\
                     ",
-                    arch, added, updated, removed
-                )
+                    arch, added, updated, removed, renamed, unchanged
+                );
+                let by_arch = |list: &str| format!("#### {}\n{}\n", arch, list);
+                [
+                    changes,
+                    by_arch(&added),
+                    by_arch(&updated),
+                    by_arch(&removed),
+                ]
             })
-            .reduce(|mut acc, e| {
-                acc.push_str(e.as_str());
-                acc
+            .fold(
+                [String::new(), String::new(), String::new(), String::new()],
+                |mut acc, e| {
+                    for (acc_val, e_val) in acc.iter_mut().zip(e) {
+                        acc_val.push_str(e_val.as_str());
+                    }
+                    acc
+                },
+            );
+        // Only list coverage gaps if they were looked for
+        let coverage_section = self.coverage.as_ref().map_or("".into(), |coverage| {
+            format!(
+                "### Coverage\n\
+                {}:\n\
+                {}",
+                self.coverage_note(),
+                coverage
+                    .iter()
+                    .map(|gap| format!(" - {}\n", Self::coverage_text(gap)))
+                    .reduce(|mut acc, e| {
+                        acc.push_str(e.as_str());
+                        acc
+                    })
+                    .unwrap_or("None\n".into())
+            )
+        });
+
+        let [changes, added, updated, removed] = match options.by_package {
+            true => self.by_package_lists(options),
+            false => pkgs_by_arch,
+        };
+        // The matrix only stands in for the full changes, the lists of one category stay as they are
+        let changes = match options.matrix {
+            true => format!("{}\n", self.matrix(options)),
+            false => changes,
+        };
+
+        TemplateContext::from([
+            (
+                "heading",
+                format!(
+                    "nix-update-report{}",
+                    title.clone().map_or("".into(), |val| format!(" - {}", val))
+                ),
+            ),
+            ("title", title.clone().unwrap_or("".into())),
+            ("version", template::VERSION.into()),
+            ("generated_at", generated_at.clone()),
+            ("footer", template::footer(&generated_at)),
+            ("stats", stats),
+            ("changes", changes),
+            ("added", added),
+            ("updated", updated),
+            ("removed", removed),
+            ("added_count", self.added_pkgs().to_string()),
+            ("updated_count", self.updated_pkgs().to_string()),
+            ("removed_count", self.removed_pkgs().to_string()),
+            ("archs", self.total_archs.to_string()),
+            ("coverage_section", coverage_section),
+            (
+                "revision_line",
+                self.revisions
+                    .as_ref()
+                    .map_or("".into(), |(previous, next)| {
+                        format!("Revision: `{} -> {}`\n", previous, next)
+                    }),
+            ),
+        ])
+    }
+
+    /// Generate comparison report as a self-contained html document with every list collapsed. Takes the same options as the markdown report, but table, by_package, matrix, and toc only change markdown.
+    pub fn generate_html_report(
+        &self,
+        title: &Option<String>,
+        options: &FlakeReportOptions,
+    ) -> String {
+        let has_renames = self.pkg_data.values().any(|data| data.renamed.is_some());
+
+        // One row per arch
+        let mut arch_header: Vec<String> = vec![
+            "Arch".into(),
+            "Added".into(),
+            "Updated".into(),
+            "Removed".into(),
+        ];
+        if has_renames {
+            arch_header.push("Renamed".into());
+        }
+        arch_header.extend(["Unchanged".into(), "Unparsable".into(), "Total".into()]);
+        let arch_rows: Vec<Vec<String>> = self
+            .pkg_data
+            .iter()
+            .map(|(arch, data)| {
+                let mut row = vec![
+                    arch.clone(),
+                    data.added.len().to_string(),
+                    data.updated.len().to_string(),
+                    data.removed.len().to_string(),
+                ];
+                if has_renames {
+                    row.push(data.renamed.as_ref().map_or(0, |val| val.len()).to_string());
+                }
+                row.extend([
+                    data.unchanged.to_string(),
+                    data.unparsable_pkgs.to_string(),
+                    data.total_pkgs.to_string(),
+                ]);
+                row
             })
-            .unwrap_or("".into());
+            .collect();
 
-        report.push_str("### Pkg Changes\n");
-        report.push_str(pkgs_by_arch.as_str());
-        report
+        let mut totals: Vec<(&str, String)> = vec![
+            ("Added Pkgs", self.added_pkgs().to_string()),
+            (
+                "Updated Pkgs",
+                format!(
+                    "{} ({} of total)",
+                    self.updated_pkgs(),
+                    percent(self.updated_pkgs(), self.total_pkgs())
+                ),
+            ),
+            (
+                "Bumps",
+                format!(
+                    "Major: {}, Minor: {}, Patch: {}, Other: {}, Unknown: {}",
+                    self.bump_count(BumpLevel::Major),
+                    self.bump_count(BumpLevel::Minor),
+                    self.bump_count(BumpLevel::Patch),
+                    self.bump_count(BumpLevel::Other),
+                    self.bump_count(BumpLevel::Unknown)
+                ),
+            ),
+            ("Removed Pkgs", self.removed_pkgs().to_string()),
+        ];
+        if has_renames {
+            totals.push((
+                "Renamed Pkgs",
+                self.pkg_data
+                    .values()
+                    .filter_map(|data| data.renamed.as_ref().map(|val| val.len()))
+                    .sum::<usize>()
+                    .to_string(),
+            ));
+        }
+        totals.extend([
+            (
+                "Unchanged Pkgs",
+                self.pkg_data
+                    .values()
+                    .map(|data| data.unchanged)
+                    .sum::<usize>()
+                    .to_string(),
+            ),
+            (
+                "Unparsable Pkgs",
+                self.pkg_data
+                    .values()
+                    .map(|data| data.unparsable_pkgs)
+                    .sum::<usize>()
+                    .to_string(),
+            ),
+            ("Pkgs", self.total_pkgs().to_string()),
+            (
+                "Net Pkgs",
+                net_change(self.added_pkgs(), self.removed_pkgs()),
+            ),
+            ("Added Archs", self.added_archs.len().to_string()),
+            ("Removed Archs", self.removed_archs.len().to_string()),
+            ("Archs", self.total_archs.to_string()),
+            (
+                "Net Archs",
+                net_change(self.added_archs.len(), self.removed_archs.len()),
+            ),
+        ]);
+
+        // One collapsed section per arch holding a collapsed list per category
+        let pkgs_by_arch: String = self
+            .report_sections(options.dedup_archs)
+            .into_iter()
+            .map(|(arch, pkgs)| {
+                let escape_all = |lines: Vec<String>| -> Vec<String> {
+                    lines.iter().map(|val| html::escape(val)).collect()
+                };
+
+                // Descriptions are only shown for added and updated packages, the same as in markdown
+                let description = |old: Option<&Package>, new: &Package| {
+                    let changed = old.is_some_and(|old| Self::description_changed(old, new));
+                    Self::description_text(old, new, options.max_description_len)
+                        .filter(|_| {
+                            options.show_descriptions
+                                || (options.show_description_changes && changed)
+                        })
+                        .map_or("".into(), |val| format!(" ({})", val))
+                };
+
+                // Only the first entries are listed if there is a limit
+                let mut lists = html::limited_details(
+                    "Added",
+                    &escape_all(
+                        pkgs.added
+                            .iter()
+                            .map(|pkg| format!("{}{}", Self::pkg_text(pkg), description(None, pkg)))
+                            .collect(),
+                    ),
+                    options.limit,
+                );
+                lists.push_str(&html::limited_details(
+                    "Updated",
+                    &escape_all(
+                        Self::ordered_updates(&pkgs.updated, options.update_order)
+                            .into_iter()
+                            .map(|(old_pkg, pkg, compare_data)| {
+                                format!(
+                                    "{}{}",
+                                    Self::change_text(compare_data),
+                                    description(Some(old_pkg), pkg)
+                                )
+                            })
+                            .collect(),
+                    ),
+                    options.limit,
+                ));
+                lists.push_str(&html::limited_details(
+                    "Removed",
+                    &escape_all(pkgs.removed.iter().map(Self::pkg_text).collect()),
+                    options.limit,
+                ));
+                if let Some(renamed) = &pkgs.renamed {
+                    lists.push_str(&html::details(
+                        "Renamed",
+                        &escape_all(
+                            renamed
+                                .iter()
+                                .map(|(old_pkg, new_pkg)| {
+                                    format!("{} -> {}", old_pkg.get_name(), Self::pkg_text(new_pkg))
+                                })
+                                .collect(),
+                        ),
+                    ));
+                }
+                if let Some(unchanged) = &pkgs.unchanged_list {
+                    lists.push_str(&html::details(
+                        "Unchanged",
+                        &escape_all(unchanged.iter().map(Self::pkg_text).collect()),
+                    ));
+                }
+
+                html::section(arch, &lists)
+            })
+            .collect();
+
+        let body = format!(
+            "{}\
+            <h3>Stats</h3>\n\
+            {}\
+            <h4>By Arch</h4>\n\
+            {}\
+            <h4>Totals</h4>\n\
+            {}\
+            <h3>Pkg Changes</h3>\n\
+            {}\
+            {}",
+            self.revisions
+                .as_ref()
+                .map_or("".into(), |(previous, next)| format!(
+                    "<p>Revision: <code>{} -&gt; {}</code></p>\n",
+                    html::escape(previous),
+                    html::escape(next)
+                )),
+            self.filter.as_ref().map_or("".into(), |val| format!(
+                "<p>Only counting packages {}</p>\n",
+                html::escape_code(val)
+            )),
+            html::table(&arch_header, &arch_rows),
+            html::table(
+                &["Stat".into(), "Count".into()],
+                &totals
+                    .into_iter()
+                    .map(|(name, count)| vec![name.into(), count])
+                    .collect::<Vec<Vec<String>>>()
+            ),
+            pkgs_by_arch,
+            self.coverage.as_ref().map_or("".into(), |coverage| format!(
+                "<h3>Coverage</h3>\n{}",
+                html::details(
+                    &self.coverage_note(),
+                    &coverage
+                        .iter()
+                        .map(|gap| html::escape(&Self::coverage_text(gap)))
+                        .collect::<Vec<String>>()
+                )
+            ))
+        );
+
+        html::document(
+            &format!(
+                "nix-update-report{}",
+                title.clone().map_or("".into(), |val| format!(" - {}", val))
+            ),
+            &body,
+        )
     }
 }
@@ -0,0 +1,117 @@
+// Helpers for rendering reports as html
+
+use crate::template;
+
+/// Keeps tables and lists readable without any outside stylesheet
+const STYLE: &str = "body { font-family: sans-serif; max-width: 60em; margin: 2em auto; padding: 0 1em; }\n\
+    table { border-collapse: collapse; margin-bottom: 1em; }\n\
+    th, td { border: 1px solid #ccc; padding: 0.3em 0.8em; text-align: left; }\n\
+    th { background: #f3f3f3; }\n\
+    details { margin: 0.3em 0; }\n\
+    summary { cursor: pointer; font-weight: bold; }\n\
+    code { background: #f3f3f3; padding: 0 0.2em; }\n";
+
+/// Escapes text so it can be placed inside html
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Escapes text and turns `backtick` spans into code tags
+pub(crate) fn escape_code(text: &str) -> String {
+    escape(text)
+        .split('`')
+        .enumerate()
+        .map(|(i, part)| match i % 2 {
+            1 => format!("<code>{}</code>", part),
+            _ => part.into(),
+        })
+        .collect()
+}
+
+/// Wraps content (already html) into a collapsed section
+pub(crate) fn section(summary: &str, content: &str) -> String {
+    format!(
+        "<details>\n\
+        <summary>{}</summary>\n\
+        {}\
+        </details>\n",
+        escape(summary),
+        content
+    )
+}
+
+/// Wraps items (already html) into a collapsed list with the item count in the summary
+pub(crate) fn details(summary: &str, items: &[String]) -> String {
+    limited_details(summary, items, None)
+}
+
+/// Wraps items (already html) into a collapsed list of at most limit items, noting how many were cut. The summary still counts every item.
+pub(crate) fn limited_details(summary: &str, items: &[String], limit: Option<usize>) -> String {
+    let (kept, hidden) = template::limit_list(items, limit);
+    let mut list = match kept.is_empty() {
+        true if hidden == 0 => "<p>None</p>\n".into(),
+        true => "".into(),
+        false => format!(
+            "<ul>\n{}</ul>\n",
+            kept.iter()
+                .map(|val| format!("<li>{}</li>\n", val))
+                .collect::<String>()
+        ),
+    };
+    if hidden > 0 {
+        list.push_str(&format!("<p>...and {} more</p>\n", hidden));
+    }
+
+    section(&format!("{} ({})", summary, items.len()), &list)
+}
+
+/// Renders a table with a header row. Cells are escaped.
+pub(crate) fn table(header: &[String], rows: &[Vec<String>]) -> String {
+    let row = |cells: &[String], tag: &str| {
+        format!(
+            "<tr>{}</tr>\n",
+            cells
+                .iter()
+                .map(|val| format!("<{}>{}</{}>", tag, escape(val), tag))
+                .collect::<String>()
+        )
+    };
+
+    format!(
+        "<table>\n{}{}</table>\n",
+        row(header, "th"),
+        rows.iter()
+            .map(|cells| row(cells, "td"))
+            .collect::<String>()
+    )
+}
+
+/// Wraps a body into a self-contained html document, ending with when and with what version it was generated
+pub(crate) fn document(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n\
+        <html>\n\
+        <head>\n\
+        <meta charset=\"utf-8\">\n\
+        <title>{}</title>\n\
+        <style>\n{}</style>\n\
+        </head>\n\
+        <body>\n\
+        <h2>{}</h2>\n\
+        <p>Report generated using <a href=\"https://github.com/aldenparker/nix-update-report.git\"><code>nix-update-report</code></a>.</p>\n\
+        {}\
+        <hr>\n\
+        <p>Generated by nix-update-report v{} at {}</p>\n\
+        </body>\n\
+        </html>\n",
+        escape(title),
+        STYLE,
+        escape(title),
+        body,
+        template::VERSION,
+        template::generated_at()
+    )
+}
@@ -0,0 +1,29 @@
+// Library interface for comparing flakes and nixpkgs commits
+
+mod error;
+pub mod flakes;
+mod html;
+pub mod lock;
+pub mod log;
+pub mod nixpkgs;
+pub mod packages;
+pub mod report_diff;
+pub mod template;
+mod webhook;
+
+pub use error::Error;
+pub use flakes::{
+    Flake, FlakeCompareData, FlakeCompareOptions, FlakeOptions, FlakeOutput, FlakeParseError,
+    FlakeReportOptions,
+};
+pub use lock::{FlakeLock, FlakeLockCompareData, LockParseError, LockedInput};
+pub use nixpkgs::{
+    CommitDetails, CompareParseError, DEFAULT_SKIP_PREFIXES, KeywordCommit, Nixpkgs, NixpkgsReport,
+    NixpkgsReportOptions, NixpkgsUpdate, PkgAttr, REPORT_SCHEMA_VERSION, ReportParseError,
+};
+pub use packages::{
+    BumpLevel, NameFilter, Package, PkgCompareData, PkgVersion, PreRelease, UpdateOrder,
+    VersionDirection,
+};
+pub use report_diff::{ListDiff, ReportDiff};
+pub use webhook::WebhookStyle;
@@ -0,0 +1,188 @@
+// Structs used to compare the inputs pinned in two flake.lock files
+
+use serde_json::Value;
+use std::{collections::BTreeMap, error, fmt};
+
+// --- LOCK
+/// What a single flake input is pinned to
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct LockedInput {
+    /// The locked revision, none for inputs that aren't from a repository (ex. tarballs)
+    pub rev: Option<String>,
+    /// The hash of the locked source
+    pub nar_hash: Option<String>,
+}
+
+impl LockedInput {
+    /// The revision if there is one, otherwise the source hash
+    pub fn pin(&self) -> &str {
+        self.rev
+            .as_deref()
+            .or(self.nar_hash.as_deref())
+            .unwrap_or("unknown")
+    }
+}
+
+/// All of the locked inputs in a flake.lock by node name (sorted so reports are deterministic)
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct FlakeLock(BTreeMap<String, LockedInput>);
+
+impl FlakeLock {
+    /// Reads a flake.lock. The root node and nodes that aren't locked (ex. follows) are skipped.
+    pub fn new(lock_json: &Value) -> Result<FlakeLock, LockParseError> {
+        let nodes = lock_json["nodes"]
+            .as_object()
+            .ok_or(LockParseError::MissingNodes)?;
+        let root = lock_json["root"].as_str().unwrap_or("root");
+
+        let mut inputs: BTreeMap<String, LockedInput> = BTreeMap::new();
+        for (name, node) in nodes.iter() {
+            if name == root {
+                continue;
+            }
+
+            if let Some(locked) = node.get("locked") {
+                inputs.insert(
+                    name.clone(),
+                    LockedInput {
+                        rev: locked["rev"].as_str().map(|val| val.into()),
+                        nar_hash: locked["narHash"].as_str().map(|val| val.into()),
+                    },
+                );
+            }
+        }
+
+        Ok(FlakeLock(inputs))
+    }
+}
+
+/// Errors produced when flake.lock json can't be read
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum LockParseError {
+    /// The json has no nodes map
+    MissingNodes,
+}
+
+impl fmt::Display for LockParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockParseError::MissingNodes => write!(f, "flake.lock json has no nodes"),
+        }
+    }
+}
+
+impl error::Error for LockParseError {}
+
+// --- LOCK COMPARE
+/// Lock comparison data for all inputs in the flake.lock
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct FlakeLockCompareData {
+    /// Inputs that were added as (name, input)
+    added: Vec<(String, LockedInput)>,
+    /// Inputs that were pinned to something new as (name, old input, new input)
+    updated: Vec<(String, LockedInput, LockedInput)>,
+    /// Inputs that were removed as (name, input)
+    removed: Vec<(String, LockedInput)>,
+    /// The total number of inputs in the new lock
+    total_inputs: usize,
+}
+
+impl FlakeLockCompareData {
+    pub fn new(old: &FlakeLock, new: &FlakeLock) -> FlakeLockCompareData {
+        let mut compare_data = FlakeLockCompareData {
+            added: vec![],
+            updated: vec![],
+            removed: vec![],
+            total_inputs: new.0.len(),
+        };
+
+        // Both maps are sorted so the lists come out sorted too
+        for (name, old_input) in old.0.iter() {
+            match new.0.get(name) {
+                Some(new_input) if new_input != old_input => {
+                    compare_data
+                        .updated
+                        .push((name.clone(), old_input.clone(), new_input.clone()));
+                }
+                Some(_) => (),
+                None => compare_data.removed.push((name.clone(), old_input.clone())),
+            }
+        }
+
+        for (name, new_input) in new.0.iter() {
+            if !old.0.contains_key(name) {
+                compare_data.added.push((name.clone(), new_input.clone()));
+            }
+        }
+
+        compare_data
+    }
+
+    /// Checks if any inputs were removed
+    pub fn has_removals(&self) -> bool {
+        !self.removed.is_empty()
+    }
+
+    /// Generate comparison report in markdown
+    pub fn generate_report(&self, title: &Option<String>) -> String {
+        let list = |lines: Vec<String>| {
+            lines
+                .into_iter()
+                .reduce(|mut acc, e| {
+                    acc.push_str(e.as_str());
+                    acc
+                })
+                .unwrap_or("None\n".into())
+        };
+
+        format!(
+            "## nix-update-report{}\n\
+            Report generated using [`nix-update-report`](https://github.com/aldenparker/nix-update-report.git).\n\
+            \n\
+            ### Stats\n\
+            Added Inputs: {}\n\
+            Updated Inputs: {}\n\
+            Removed Inputs: {}\n\
+            Inputs: {}\n\
+            \n\
+            ### Added\n\
+            {}\n\
+            ### Updated\n\
+            {}\n\
+            ### Removed\n\
+            {}\
+            ",
+            title.clone().map_or("".into(), |val| format!(" - {}", val)),
+            self.added.len(),
+            self.updated.len(),
+            self.removed.len(),
+            self.total_inputs,
+            list(
+                self.added
+                    .iter()
+                    .map(|(name, input)| format!(" - {}: {}\n", name, input.pin()))
+                    .collect()
+            ),
+            list(
+                self.updated
+                    .iter()
+                    .map(|(name, old_input, new_input)| {
+                        // The same revision with a new hash means the source was refetched
+                        match old_input.pin() == new_input.pin() {
+                            true => format!(" - {}: {}, hash changed\n", name, new_input.pin()),
+                            false => {
+                                format!(" - {}: {} -> {}\n", name, old_input.pin(), new_input.pin())
+                            }
+                        }
+                    })
+                    .collect()
+            ),
+            list(
+                self.removed
+                    .iter()
+                    .map(|(name, input)| format!(" - {}: {}\n", name, input.pin()))
+                    .collect()
+            )
+        )
+    }
+}
@@ -0,0 +1,56 @@
+// Leveled logging to stderr, turned up with --verbose. This is a small stand-in for the log and env_logger crates so the crate keeps its few dependencies, the level only comes from -v, -vv, and so on (RUST_LOG is not read).
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// How much detail a log message carries. Each level also shows the ones below it.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub enum Level {
+    /// What the tool is doing and how much it read (ex. packages per arch)
+    Debug = 1,
+    /// Every parsed commit, package, and version along with how it was parsed
+    Trace = 2,
+}
+
+/// The highest level that gets printed, 0 prints nothing
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(0);
+
+/// Sets how verbose logging is (0 is silent, 1 is debug, 2 or more is trace)
+pub fn set_verbosity(verbosity: u8) {
+    MAX_LEVEL.store(verbosity, Ordering::Relaxed);
+}
+
+/// Checks if messages at the level get printed
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+/// Prints a message to stderr if its level is enabled
+pub fn log(level: Level, message: &str) {
+    if enabled(level) {
+        let tag = match level {
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        };
+        eprintln!("[{}] {}", tag, message);
+    }
+}
+
+/// Logs a formatted debug message, only formatting it if debug is enabled
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Debug) {
+            $crate::log::log($crate::log::Level::Debug, &format!($($arg)*));
+        }
+    };
+}
+
+/// Logs a formatted trace message, only formatting it if trace is enabled
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::Level::Trace) {
+            $crate::log::log($crate::log::Level::Trace, &format!($($arg)*));
+        }
+    };
+}
+
+pub(crate) use {debug, trace};
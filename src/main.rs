@@ -1,20 +1,269 @@
-mod flakes;
-mod nixpkgs;
+mod completions;
 
-use clap::{Parser, Subcommand};
-use flakes::{Flake, FlakeCompareData};
-use nixpkgs::Nixpkgs;
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use completions::Shell;
+use nix_update_report::{
+    CommitDetails, Error, Flake, FlakeCompareData, FlakeCompareOptions, FlakeLock,
+    FlakeLockCompareData, FlakeOptions, FlakeOutput, FlakeReportOptions, NameFilter, Nixpkgs,
+    NixpkgsReport, NixpkgsReportOptions, ReportDiff, UpdateOrder, WebhookStyle, log, template,
+};
+use regex::Regex;
 use serde_json::Value;
-use std::{fs::File, io::Write, process::Command};
+use std::{
+    collections::HashMap,
+    env, fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process::{self, Child, Command, ExitCode, Stdio},
+    sync::{
+        OnceLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use time::{Date, macros::format_description};
 
 /// Small application to compare nixpkgs commits.
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Cli {
+    /// Don't print progress messages (errors are still printed)
+    #[arg(short, long, global = true)]
+    quiet: bool,
+    /// Print debug logs to stderr (-vv also prints every parsed commit, package, and version)
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Add the report to the top of an existing out file (or right after a `<!-- nix-update-report -->` line) instead of overwriting it
+    #[arg(long, global = true)]
+    append: bool,
+    /// The nix executable to run
+    #[arg(long, global = true, env = "NIX_BIN", default_value = "nix")]
+    nix_bin: String,
+    /// The gh executable to run for the gh backend
+    #[arg(long, global = true, env = "GH_BIN", default_value = "gh")]
+    gh_bin: String,
+    /// The curl executable to run for the rest backend and webhooks
+    #[arg(long, global = true, env = "CURL_BIN", default_value = "curl")]
+    curl_bin: String,
+    /// Kill a nix, gh, git, or curl command and exit with an error if it runs longer than this many seconds
+    #[arg(long, global = true, env = "NIX_UPDATE_REPORT_TIMEOUT", value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+    timeout: Option<u64>,
+    /// Where nix flake show output is cached between runs [default: $XDG_CACHE_HOME/nix-update-report]
+    #[arg(long, global = true, env = "NIX_UPDATE_REPORT_CACHE_DIR")]
+    cache_dir: Option<String>,
+    /// Always run nix flake show instead of reading its cached output (flakes without a clean revision, ex. a dirty git tree or a path that isn't a git repo, are never cached)
+    #[arg(long, global = true)]
+    no_cache: bool,
     #[command(subcommand)]
     command: Option<Commands>,
 }
 
+/// The output formats a report can be rendered in
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ReportFormat {
+    /// Markdown report
+    Markdown,
+    /// Structured json report
+    Json,
+    /// Self-contained html document with collapsible sections
+    Html,
+    /// Keep a Changelog release with Added, Changed, and Removed sections
+    Changelog,
+    /// Short Slack or Discord message json, posted to --webhook-url or printed if there is none
+    Webhook,
+}
+
+/// The output formats a flake report can be rendered in
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum FlakeReportFormat {
+    /// Markdown report
+    Markdown,
+    /// Self-contained html document with collapsible sections
+    Html,
+    /// Keep a Changelog release with Added, Changed, and Removed sections
+    Changelog,
+    /// Short Slack or Discord message json, posted to --webhook-url or printed if there is none
+    Webhook,
+}
+
+/// The chats a webhook message can be shaped for
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum WebhookChat {
+    /// Slack incoming webhook
+    Slack,
+    /// Discord webhook
+    Discord,
+}
+
+/// Where a webhook report goes (only used with --format webhook)
+#[derive(Args, Debug)]
+struct WebhookArgs {
+    /// Post the webhook report to this Slack or Discord incoming webhook url instead of printing it
+    #[arg(long, env = "NIX_UPDATE_REPORT_WEBHOOK_URL", hide_env_values = true)]
+    webhook_url: Option<String>,
+    /// Shape the message for this chat instead of guessing it from the url (Slack if there is no url)
+    #[arg(long, value_enum)]
+    webhook_style: Option<WebhookChat>,
+}
+
+impl WebhookArgs {
+    /// The chat to shape the payload for
+    fn style(&self) -> WebhookStyle {
+        match (self.webhook_style, &self.webhook_url) {
+            (Some(WebhookChat::Slack), _) => WebhookStyle::Slack,
+            (Some(WebhookChat::Discord), _) => WebhookStyle::Discord,
+            (None, Some(url)) => WebhookStyle::from_url(url),
+            (None, None) => WebhookStyle::default(),
+        }
+    }
+
+    /// Posts the payload to the webhook, or prints it if there is no url
+    fn send(&self, payload: &str) -> Result<(), Error> {
+        match &self.webhook_url {
+            Some(url) => post_webhook(url, payload),
+            None => {
+                println!("{}", payload);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Flags that make the command fail on concerning changes (useful for CI)
+#[derive(Args, Debug)]
+struct GateArgs {
+    /// Exit with an error if any packages were removed (the report is still written)
+    #[arg(long)]
+    fail_on_removed: bool,
+    /// Exit with an error if any package versions went backwards (the report is still written)
+    #[arg(long)]
+    fail_on_downgrade: bool,
+    /// Print removals and downgrades as Github Actions annotations to stdout (errors when they fail the command, warnings otherwise)
+    #[arg(long, env = "NIX_UPDATE_REPORT_GITHUB_ANNOTATIONS")]
+    github_annotations: bool,
+}
+
+impl GateArgs {
+    /// Prints the removals and downgrades as Github Actions workflow commands if asked to
+    fn annotate(&self, removals: &[String], downgrades: &[String]) {
+        if !self.github_annotations {
+            return;
+        }
+
+        // Workflow command messages end at a newline, so encode the characters Github decodes
+        let escape = |message: &str| {
+            message
+                .replace('%', "%25")
+                .replace('\r', "%0D")
+                .replace('\n', "%0A")
+        };
+        let level = |fails: bool| if fails { "error" } else { "warning" };
+        for removal in removals {
+            println!(
+                "::{} title=Removed package::{}",
+                level(self.fail_on_removed),
+                escape(removal)
+            );
+        }
+        for downgrade in downgrades {
+            println!(
+                "::{} title=Downgraded package::{}",
+                level(self.fail_on_downgrade),
+                escape(downgrade)
+            );
+        }
+    }
+
+    /// Fails if the report breaks any of the requested gates
+    fn check(&self, has_removals: bool, has_downgrades: bool) -> Result<(), Error> {
+        let mut failures = vec![];
+        if self.fail_on_removed && has_removals {
+            failures.push("Report contains removed packages".into());
+        }
+        if self.fail_on_downgrade && has_downgrades {
+            failures.push("Report contains downgraded packages".into());
+        }
+
+        match failures.is_empty() {
+            true => Ok(()),
+            false => Err(Error::Check(failures)),
+        }
+    }
+}
+
+/// The ways package lists can be grouped in a report
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum GroupBy {
+    /// Group by top-level package set (ex. python3Packages)
+    Set,
+}
+
+/// The flake outputs that can be compared
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Output {
+    /// Packages, falling back to legacyPackages
+    Packages,
+    /// Apps (these usually have no version so they mostly show up as added or removed)
+    Apps,
+    /// Checks
+    Checks,
+    /// Development shells
+    #[value(name = "devShells")]
+    DevShells,
+}
+
+impl Output {
+    /// The library output this one picks
+    fn flake_output(self) -> FlakeOutput {
+        match self {
+            Output::Packages => FlakeOutput::Packages,
+            Output::Apps => FlakeOutput::Apps,
+            Output::Checks => FlakeOutput::Checks,
+            Output::DevShells => FlakeOutput::DevShells,
+        }
+    }
+}
+
+/// The orders the updated packages of a report can be listed in
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum SortBy {
+    /// Alphabetically by package name (still grouped by arch or package set)
+    Name,
+    /// Biggest version bump first, so major updates are seen first
+    Version,
+}
+
+impl SortBy {
+    /// The library order this one picks
+    fn update_order(self) -> UpdateOrder {
+        match self {
+            SortBy::Name => UpdateOrder::Name,
+            SortBy::Version => UpdateOrder::Bump,
+        }
+    }
+}
+
+/// The formats a flake's package inventory can be printed in
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum InventoryFormat {
+    /// Markdown list per arch
+    Markdown,
+    /// Json map of packages by arch and name, showing how each version was parsed
+    Json,
+}
+
+/// The backends that can be used to fetch commits
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Backend {
+    /// Call the Github REST api directly with curl (authenticates with GITHUB_TOKEN if set)
+    Rest,
+    /// Use the gh cli (must be installed and authenticated)
+    Gh,
+    /// Read the commits from a local clone with git log (see --repo-path)
+    Git,
+}
+
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Compares two nixpkgs hashes and makes a report
@@ -23,139 +272,1964 @@ enum Commands {
         previous: String,
         /// The head commit hash
         next: String,
-        /// Set a custom output path for the report
+        /// Set a title for the report generated
+        #[arg(short, long)]
+        title: Option<String>,
+        /// Set a custom output path for the report (- for stdout)
         #[arg(short, long, default_value = "report.md")]
         out: String,
+        /// Set the format of the report
+        #[arg(short, long, value_enum, default_value_t = ReportFormat::Markdown)]
+        format: ReportFormat,
+        /// Head a changelog report with this version and today's date instead of Unreleased
+        #[arg(long = "version", value_name = "VERSION")]
+        release_version: Option<String>,
+        /// Set the backend used to fetch the commits
+        #[arg(short, long, value_enum, default_value_t = Backend::Rest)]
+        backend: Backend,
+        /// The Github repository to compare commits in
+        #[arg(short, long, default_value = "NixOS/nixpkgs", value_parser = parse_repo)]
+        repo: String,
+        /// The local clone to read commits from when using the git backend
+        #[arg(long, default_value = ".")]
+        repo_path: String,
+        /// Read the commits from a file (or - for stdin) instead of Github. Takes either the compare json or one commit message per line.
+        #[arg(short, long)]
+        commits_file: Option<String>,
+        /// Group the package lists in a markdown report
+        #[arg(short, long, value_enum)]
+        group_by: Option<GroupBy>,
+        /// Pair removed and added packages with similar names as renames
+        #[arg(long)]
+        detect_renames: bool,
+        /// Link package names to search.nixos.org
+        #[arg(long)]
+        link_packages: bool,
+        /// Link each package to the most recent commit that touched it
+        #[arg(long)]
+        link_commits: bool,
+        /// List commits whose full message mentions one of these comma separated keywords under Security-Relevant, ignoring case (CVE, security, and vulnerability if none are given)
+        #[arg(
+            long,
+            value_name = "KEYWORDS",
+            value_delimiter = ',',
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "CVE,security,vulnerability"
+        )]
+        security_keywords: Option<Vec<String>>,
+        /// Only list the first N entries of each package list in a markdown or html report
+        #[arg(long)]
+        limit: Option<usize>,
+        /// Set the order the updated packages are listed in
+        #[arg(long, value_enum, default_value_t = SortBy::Name)]
+        sort_by: SortBy,
+        /// Only keep packages whose name matches this regex
+        #[arg(long, value_parser = parse_filter)]
+        filter: Option<Regex>,
+        /// Drop packages whose name matches this regex (applied after --filter)
+        #[arg(long, value_parser = parse_filter)]
+        exclude: Option<Regex>,
+        /// Also print a one line summary of the changes to stdout
+        #[arg(long)]
+        stat: bool,
+        /// Render the report with a template file holding {{placeholder}}s instead of the built in markdown (replaces --format)
+        #[arg(long)]
+        template: Option<String>,
+        /// How many times to retry a request when Github rate limits it
+        #[arg(long, default_value_t = 3)]
+        retries: u32,
+        /// Only use commits authored on or after this date (YYYY-MM-DD)
+        #[arg(long, value_parser = parse_date)]
+        since: Option<Date>,
+        /// Only use commits authored on or before this date (YYYY-MM-DD)
+        #[arg(long, value_parser = parse_date)]
+        until: Option<Date>,
+        /// Only use commits whose subject starts with this branch tag (ex. 24.05 for `[24.05] foo: 1.0 -> 2.0`)
+        #[arg(long, value_name = "TAG")]
+        branch_tag: Option<String>,
+        /// Count commits that can't be parsed and start with one of these comma separated prefixes as non-package commits instead of unparsable ones (Merge, treewide, and maintainers: by default, pass an empty list to count them all as unparsable)
+        #[arg(long, value_name = "PREFIXES", value_delimiter = ',')]
+        skip_prefixes: Option<Vec<String>>,
+        /// Don't check that the refs look like commit hashes or branch or tag names before asking Github for them
+        #[arg(long)]
+        no_validate: bool,
+        /// Print the command that would fetch the commits and exit without running it (only the first page of a Github compare is shown since the page count is only known after fetching)
+        #[arg(long)]
+        print_command: bool,
+        #[command(flatten)]
+        webhook: WebhookArgs,
+        #[command(flatten)]
+        gates: GateArgs,
     },
 
     /// Compares two versions of a flake (or different flakes) and makes a report based on it's packages (does not work on nixpkgs repo)
     Flake {
         /// The flake url pointing towards the previous revision, tag, etc.
-        previous_url: String,
+        previous_url: Option<String>,
         /// The flake url pointing towards the next revision, tag, etc.
-        next_url: String,
+        next_url: Option<String>,
+        /// Read the previous flake from a saved `nix flake show --json` output instead of a url
+        #[arg(long)]
+        previous_file: Option<String>,
+        /// Read the next flake from a saved `nix flake show --json` output instead of a url
+        #[arg(long)]
+        next_file: Option<String>,
+        /// Compare a single flake url at two git refs (pass --ref twice, previous first)
+        #[arg(long = "ref", value_name = "REF")]
+        refs: Vec<String>,
+        /// Pass an extra argument to nix flake show and nix flake metadata (repeatable, ex. --nix-arg=--impure). Each one is passed verbatim as a single argument.
+        #[arg(long = "nix-arg", value_name = "ARG", allow_hyphen_values = true)]
+        nix_args: Vec<String>,
+        /// Set a title for the report generated
+        #[arg(short, long)]
+        title: Option<String>,
+        /// Set a custom output path for the report (- for stdout)
+        #[arg(short, long, default_value = "report.md")]
+        out: String,
+        /// Set the format of the report
+        #[arg(short, long, value_enum, default_value_t = FlakeReportFormat::Markdown)]
+        format: FlakeReportFormat,
+        /// Head a changelog report with this version and today's date instead of Unreleased
+        #[arg(long = "version", value_name = "VERSION")]
+        release_version: Option<String>,
+        /// Render the package lists as markdown tables
+        #[arg(long)]
+        table: bool,
+        /// List each change once with the archs it happened on instead of under every arch
+        #[arg(long)]
+        by_package: bool,
+        /// List changes that are the same on every arch once under a Common section
+        #[arg(long)]
+        dedup_archs: bool,
+        /// Start a markdown report with a table of contents linking to each section (left out of short reports)
+        #[arg(long)]
+        toc: bool,
+        /// Render the changes as one table of packages by archs, marking what was added, updated, and removed
+        #[arg(long, conflicts_with_all = ["by_package", "dedup_archs"])]
+        matrix: bool,
+        /// Only list the first N entries of each added, updated, and removed list in a markdown or html report
+        #[arg(long)]
+        limit: Option<usize>,
+        /// Set the order the updated packages are listed in
+        #[arg(long, value_enum, default_value_t = SortBy::Name)]
+        sort_by: SortBy,
+        /// Only compare packages whose name matches this regex
+        #[arg(long, value_parser = parse_filter)]
+        filter: Option<Regex>,
+        /// Drop packages whose name matches this regex (applied after --filter)
+        #[arg(long, value_parser = parse_filter)]
+        exclude: Option<Regex>,
+        /// Show the descriptions of added and updated packages
+        #[arg(long)]
+        show_descriptions: bool,
+        /// Show the old and new description of packages whose description changed
+        #[arg(long)]
+        show_description_changes: bool,
+        /// Cut shown descriptions longer than this many characters short (0 for no limit)
+        #[arg(long, default_value_t = 80)]
+        max_description_len: usize,
+        /// Also list the packages that did not change
+        #[arg(long)]
+        include_unchanged: bool,
+        /// Pair removed and added packages with the same version and similar names as renames
+        #[arg(long)]
+        detect_renames: bool,
+        /// Compare an old package as if it had another name in the new flake (repeatable, ex. --map nodejs_18=nodejs_20)
+        #[arg(long = "map", value_name = "OLD=NEW", value_parser = parse_mapping)]
+        mappings: Vec<(String, String)>,
+        /// Read more --map mappings from a file with one OLD=NEW per line
+        #[arg(long)]
+        map_file: Option<String>,
+        /// List packages in the new flake that are missing from some of its archs
+        #[arg(long)]
+        check_coverage: bool,
+        /// Only flag packages on fewer than N archs when checking coverage
+        #[arg(long, value_name = "N", requires = "check_coverage")]
+        min_archs: Option<usize>,
+        /// Also print a one line summary of the changes to stdout
+        #[arg(long)]
+        stat: bool,
+        /// Render the report with a template file holding {{placeholder}}s instead of the built in markdown
+        #[arg(long)]
+        template: Option<String>,
+        /// Also read packages from the legacyPackages output (it is always read if packages is empty)
+        #[arg(long)]
+        legacy_output: bool,
+        /// Set which flake output is compared
+        #[arg(long, value_enum, default_value_t = Output::Packages)]
+        output: Output,
+        /// Only compare this system (repeatable, ex. --arch x86_64-linux)
+        #[arg(long = "arch", value_name = "SYSTEM")]
+        archs: Vec<String>,
+        /// Only evaluate the host system instead of passing --all-systems to nix flake show (much faster)
+        #[arg(long)]
+        current_system: bool,
+        /// Print the nix commands that would read the flakes and exit without running them
+        #[arg(long)]
+        print_command: bool,
+        #[command(flatten)]
+        webhook: WebhookArgs,
+        #[command(flatten)]
+        gates: GateArgs,
+    },
+
+    /// Lists the packages a single flake exposes, to check how they parse before comparing
+    Metadata {
+        /// The flake url to read
+        #[arg(required_unless_present = "file", conflicts_with = "file")]
+        url: Option<String>,
+        /// Read the flake from a json file (output of nix flake show --json) instead of a url
+        #[arg(long)]
+        file: Option<String>,
+        /// Set the format of the inventory
+        #[arg(short, long, value_enum, default_value_t = InventoryFormat::Markdown)]
+        format: InventoryFormat,
+        /// Set a custom output path for the inventory (- for stdout)
+        #[arg(short, long, default_value = "-")]
+        out: String,
+        /// Pass an extra argument to nix flake show and nix flake metadata (repeatable, ex. --nix-arg=--impure)
+        #[arg(long = "nix-arg", value_name = "ARG", allow_hyphen_values = true)]
+        nix_args: Vec<String>,
+        /// Set which flake output is listed
+        #[arg(long, value_enum, default_value_t = Output::Packages)]
+        output: Output,
+        /// Also read packages from the legacyPackages output (it is always read if packages is empty)
+        #[arg(long)]
+        legacy_output: bool,
+        /// Only evaluate the host system instead of passing --all-systems to nix flake show (much faster)
+        #[arg(long)]
+        current_system: bool,
+    },
+
+    /// Compares the inputs pinned in two flake.lock files
+    Lock {
+        /// The previous flake.lock
+        previous: String,
+        /// The next flake.lock
+        next: String,
+        /// Set a title for the report generated
+        #[arg(short, long)]
+        title: Option<String>,
+        /// Set a custom output path for the report (- for stdout)
+        #[arg(short, long, default_value = "report.md")]
+        out: String,
+        /// Exit with an error if any inputs were removed (the report is still written)
+        #[arg(long)]
+        fail_on_removed: bool,
+    },
+
+    /// Compares two json reports saved from the nixpkgs command and shows how they drifted
+    DiffReports {
+        /// The older json report
+        old: String,
+        /// The newer json report
+        new: String,
         /// Set a title for the report generated
         #[arg(short, long)]
         title: Option<String>,
-        /// Set a custom output path for the report
+        /// Set a custom output path for the report (- for stdout)
+        #[arg(short, long, default_value = "report.md")]
+        out: String,
+        /// Exit with an error if any packages newly show up as removed (the report is still written)
+        #[arg(long)]
+        fail_on_removed: bool,
+    },
+
+    /// Polls a branch for new commits and adds a nixpkgs report to the out file each time it moves. Stop it with Ctrl-C, any temp file a stop leaves behind is removed on the next start.
+    Watch {
+        /// The Github repository to watch
+        #[arg(short, long, default_value = "NixOS/nixpkgs", value_parser = parse_repo)]
+        repo: String,
+        /// The branch to watch
+        #[arg(long, default_value = "nixos-unstable")]
+        branch: String,
+        /// How many seconds to wait between checks
+        #[arg(long, default_value_t = 300, value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+        /// The file holding the last seen commit hash, so a restarted watch picks up where it left off
+        #[arg(long, default_value = ".nix-update-report-watch")]
+        state_file: String,
+        /// Set a title for the reports generated
+        #[arg(short, long)]
+        title: Option<String>,
+        /// Set a custom output path for the reports (- for stdout). New reports are added to the top like with --append.
         #[arg(short, long, default_value = "report.md")]
         out: String,
+        /// Set the backend used to fetch the commits (git is not supported since a local clone doesn't move on its own)
+        #[arg(short, long, value_enum, default_value_t = Backend::Rest)]
+        backend: Backend,
+        /// How many times to retry a request when Github rate limits it
+        #[arg(long, default_value_t = 3)]
+        retries: u32,
+    },
+
+    /// Prints a shell completion script to stdout
+    Completions {
+        /// The shell to generate completions for
+        #[arg(value_enum)]
+        shell: Shell,
     },
 }
 
-fn get_flake(flake_url: &String) -> Flake {
-    // Download hash data
-    let out = Command::new("sh")
-        .arg("-c")
-        .arg(format!(
-            "nix flake show '{}' --legacy --json --quiet --all-systems",
-            flake_url
-        ))
-        .output()
-        .expect(format!("Failed to execute nix flake show for flake: {}", flake_url).as_str());
+/// Makes sure a repository is given in the owner/name form
+fn parse_repo(repo: &str) -> Result<String, String> {
+    match repo.split_once('/') {
+        Some((owner, name)) if !owner.is_empty() && !name.is_empty() && !name.contains('/') => {
+            Ok(repo.into())
+        }
+        _ => Err(format!(
+            "expected a repository in the form <owner/name>, got '{}'",
+            repo
+        )),
+    }
+}
 
-    if !out.status.success() {
-        eprintln!("Flake Download Error:");
-        eprintln!("{}", String::from_utf8_lossy(&out.stderr));
-        std::process::exit(1);
+/// Checks that a ref is something git would accept as a hash, branch, or tag name. Hex refs of any length pass since short ones are valid branch and tag names (ex. 2405), a ref that doesn't exist is left for the lookup to reject.
+fn validate_ref(git_ref: &str) -> Result<(), String> {
+    let bad_char = git_ref
+        .chars()
+        .find(|val| val.is_whitespace() || val.is_control() || "~^:?*[\\".contains(*val));
+    match bad_char {
+        Some(val) => Err(format!(
+            "contains '{}' which refs can't hold",
+            val.escape_default()
+        )),
+        None if git_ref.is_empty() || git_ref == "@" => Err("is not a ref".into()),
+        None if git_ref.contains("..") || git_ref.contains("@{") || git_ref.contains("//") => {
+            Err("contains '..', '@{', or '//' which refs can't hold".into())
+        }
+        None if git_ref.starts_with(['/', '-', '.']) || git_ref.ends_with(['/', '.']) => {
+            Err("can't start with '/', '-', or '.' or end with '/' or '.'".into())
+        }
+        None if git_ref.ends_with(".lock") => Err("can't end with .lock".into()),
+        None => Ok(()),
     }
+}
 
-    // Proccess into packages type
-    let full_json: Value =
-        serde_json::from_str(String::from_utf8_lossy(&out.stdout).to_string().as_str())
-            .expect(format!("Unable to parse flake's json data : {}", flake_url).as_str());
+/// Parses a package name filter
+fn parse_filter(filter: &str) -> Result<Regex, String> {
+    Regex::new(filter).map_err(|err| format!("invalid filter regex: {}", err))
+}
 
-    Flake::new(&full_json)
+/// Parses a package name mapping in the form old=new
+fn parse_mapping(mapping: &str) -> Result<(String, String), String> {
+    match mapping.split_once('=') {
+        Some((old, new)) if !old.trim().is_empty() && !new.trim().is_empty() => {
+            Ok((old.trim().into(), new.trim().into()))
+        }
+        _ => Err(format!(
+            "expected a mapping in the form <old_name=new_name>, got '{}'",
+            mapping
+        )),
+    }
 }
 
-fn get_nixpkgs(base_hash: &String, head_hash: &String) -> Nixpkgs {
-    // Download hash data
-    let out = Command::new("sh")
-        .arg("-c")
-        .arg(format!(
-            "gh api repos/NixOS/nixpkgs/compare/{}...{}",
-            base_hash, head_hash
-        ))
-        .output()
-        .expect(format!("Failed to execute gh api call for [{}...{}]. Please check the hashes and if you are authenticated for gn.", base_hash, head_hash).as_str());
+/// Reads package name mappings from a file with one old=new per line. Blank lines and lines starting with # are skipped.
+fn read_mappings(path: &str) -> Result<Vec<(String, String)>, Error> {
+    let raw = fs::read_to_string(path).map_err(|err| Error::io(path, err))?;
+    raw.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| parse_mapping(line).map_err(|err| Error::parse(path, err)))
+        .collect()
+}
+
+/// Parses a YYYY-MM-DD date
+fn parse_date(date: &str) -> Result<Date, String> {
+    Date::parse(date, format_description!("[year]-[month]-[day]"))
+        .map_err(|_| format!("expected a date in the form <YYYY-MM-DD>, got '{}'", date))
+}
+
+/// Where the json for a flake comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum FlakeSource {
+    /// A flake url passed to nix flake show
+    Url(String),
+    /// A file holding saved nix flake show json
+    File(String),
+}
+
+/// Points a flake url at a git ref. Short github style urls take the ref as a path segment, everything else gets a ref (or rev for full commit hashes) query parameter.
+fn flake_url_at(base_url: &str, git_ref: &str) -> String {
+    let shorthand = ["github:", "gitlab:", "sourcehut:"]
+        .iter()
+        .find_map(|prefix| base_url.strip_prefix(prefix));
+    if let Some(path) = shorthand
+        && !path.contains('?')
+        && path.split('/').count() == 2
+    {
+        return format!("{}/{}", base_url, git_ref);
+    }
+
+    let param = match git_ref.len() == 40 && git_ref.chars().all(|val| val.is_ascii_hexdigit()) {
+        true => "rev",
+        false => "ref",
+    };
+    let separator = if base_url.contains('?') { '&' } else { '?' };
+    format!("{}{}{}={}", base_url, separator, param, git_ref)
+}
+
+/// Turns a single base url and two refs into the previous and next flake urls
+fn get_ref_urls(
+    previous_url: &Option<String>,
+    next_url: &Option<String>,
+    refs: &[String],
+) -> Result<(Option<String>, Option<String>), Error> {
+    let (Some(base_url), None, [previous_ref, next_ref]) = (previous_url, next_url, refs) else {
+        return Err(Error::Invalid(
+            "--ref needs exactly one flake url and to be passed exactly twice (previous ref, then next ref)".into(),
+        ));
+    };
+
+    Ok((
+        Some(flake_url_at(base_url, previous_ref)),
+        Some(flake_url_at(base_url, next_ref)),
+    ))
+}
+
+/// Pairs the flake urls and files given on the command line into a previous and next source. The urls fill in whichever side has no file.
+fn get_flake_sources(
+    previous_url: &Option<String>,
+    next_url: &Option<String>,
+    previous_file: &Option<String>,
+    next_file: &Option<String>,
+) -> Result<(FlakeSource, FlakeSource), Error> {
+    let mut urls = previous_url.iter().chain(next_url.iter());
+    let mut pick = |file: &Option<String>, side: &str| match file {
+        Some(path) => Ok(FlakeSource::File(path.clone())),
+        None => match urls.next() {
+            Some(url) => Ok(FlakeSource::Url(url.clone())),
+            None => Err(Error::Invalid(format!(
+                "Missing the {} flake, pass either a url or --{}-file",
+                side, side
+            ))),
+        },
+    };
+
+    let sources = (pick(previous_file, "previous")?, pick(next_file, "next")?);
+    if urls.next().is_some() {
+        return Err(Error::Invalid(
+            "Too many flakes given, each flake needs either a url or a file, not both".into(),
+        ));
+    }
+
+    Ok(sources)
+}
+
+/// Grabs a flake from wherever its source points, along with the revision it resolved to if that is known
+fn load_flake(
+    source: &FlakeSource,
+    options: &FlakeOptions,
+    nix_args: &[String],
+    all_systems: bool,
+) -> Result<(Flake, Option<String>), Error> {
+    match source {
+        FlakeSource::Url(flake_url) => {
+            // The revision is looked up first since it is part of the cache key
+            let (revision, locked) = get_flake_revision(flake_url, nix_args)?;
+            Ok((
+                get_flake(flake_url, options, nix_args, all_systems, &locked)?,
+                revision,
+            ))
+        }
+        FlakeSource::File(path) => {
+            let raw_json = fs::read_to_string(path).map_err(|err| Error::io(path, err))?;
+            Ok((parse_flake(&raw_json, path, options)?, None))
+        }
+    }
+}
 
+/// Looks up the revision a flake url resolves to with nix flake metadata, none if nix can't tell (ex. a path that isn't a git repo). Returns the revision to show in the report, which can be a `-dirty` one, and the clean locked revision, none for dirty trees since their contents can change without it changing. Only fails if nix can't be run at all.
+fn get_flake_revision(
+    flake_url: &str,
+    nix_args: &[String],
+) -> Result<(Option<String>, Option<String>), Error> {
+    debug(&format!("running nix flake metadata for {}", flake_url));
+    let command = flake_metadata_command(flake_url, nix_args);
+    let out = run_command(&command)?;
     if !out.status.success() {
-        eprintln!("Nix Commits Download Error:");
-        eprintln!("{}", String::from_utf8_lossy(&out.stderr));
-        std::process::exit(1);
+        return Ok((None, None));
     }
 
-    // Proccess into json
+    let Ok(metadata) = serde_json::from_slice::<Value>(&out.stdout) else {
+        return Ok((None, None));
+    };
+    let locked: Option<String> = metadata["revision"].as_str().map(|val| val.into());
+    let revision = locked.clone().or(metadata["locked"]["rev"]
+        .as_str()
+        .or(metadata["dirtyRevision"].as_str())
+        .map(|val| val.into()));
+    Ok((revision, locked))
+}
+
+/// Turns flake json into a flake, failing with a clear message if it doesn't have the expected shape
+fn parse_flake(raw_json: &str, flake: &str, options: &FlakeOptions) -> Result<Flake, Error> {
     let full_json: Value =
-        serde_json::from_str(String::from_utf8_lossy(&out.stdout).to_string().as_str()).expect(
-            format!(
-                "Unable to parse Github API's json data for [{}...{}]",
-                base_hash, head_hash
-            )
-            .as_str(),
-        );
-
-    let commits: Vec<String> = full_json
-        .get("commits")
+        serde_json::from_str(raw_json).map_err(|err| Error::parse(flake, err))?;
+    Flake::new_with_options(&full_json, options).map_err(|err| Error::parse(flake, err))
+}
+
+/// The command (program first) nix flake show is run with for a url. Without all_systems only the host system is evaluated.
+fn flake_show_command(flake_url: &str, nix_args: &[String], all_systems: bool) -> Vec<String> {
+    [
+        nix_bin(),
+        "flake",
+        "show",
+        flake_url,
+        "--legacy",
+        "--json",
+        "--quiet",
+    ]
+    .iter()
+    .chain(all_systems.then_some(&"--all-systems"))
+    .map(|val| val.to_string())
+    .chain(nix_args.iter().cloned())
+    .collect()
+}
+
+/// The command (program first) nix flake metadata is run with for a url
+fn flake_metadata_command(flake_url: &str, nix_args: &[String]) -> Vec<String> {
+    [
+        nix_bin(),
+        "flake",
+        "metadata",
+        flake_url,
+        "--json",
+        "--quiet",
+    ]
+    .iter()
+    .map(|val| val.to_string())
+    .chain(nix_args.iter().cloned())
+    .collect()
+}
+
+/// Quotes a command so it can be pasted into a shell. Arguments holding anything but plain characters are single quoted.
+fn command_line(command: &[String]) -> String {
+    command
+        .iter()
+        .map(|val| {
+            let plain = !val.is_empty()
+                && val
+                    .chars()
+                    .all(|char| char.is_ascii_alphanumeric() || "-_./:=@+,%".contains(char));
+            match plain {
+                true => val.clone(),
+                false => format!("'{}'", val.replace('\'', "'\\''")),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Runs a command (program first) and collects its output, like Command::output but stopped after --timeout
+fn run_command(command: &[String]) -> Result<process::Output, Error> {
+    let line = command_line(command);
+    let child = Command::new(&command[0])
+        .args(&command[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| Error::command(&line, format!("unable to run: {}", err)))?;
+    wait_for_output(child, &line)
+}
+
+/// What a failed command printed to stderr, or its exit status if it printed nothing
+fn failure_message(out: &process::Output) -> String {
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    match stderr.trim().is_empty() {
+        true => format!("{}", out.status),
+        false => stderr.into(),
+    }
+}
+
+/// How often a running command is checked on while waiting for it to finish before --timeout
+const TIMEOUT_POLL: Duration = Duration::from_millis(50);
+
+/// Waits for a child to finish and collects its output. If it runs past --timeout it is killed and fails with an error naming the command.
+fn wait_for_output(mut child: Child, command: &str) -> Result<process::Output, Error> {
+    let wait_error =
+        |err: io::Error| Error::command(command, format!("unable to wait for it: {}", err));
+    let Some(timeout) = TIMEOUT.get().copied().flatten() else {
+        return child.wait_with_output().map_err(wait_error);
+    };
+
+    // Read the pipes while polling, otherwise a child with lots of output fills them and never finishes
+    let read_pipe = |pipe: Option<Box<dyn Read + Send>>| {
+        thread::spawn(move || {
+            let mut buf = vec![];
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut buf);
+            }
+            buf
+        })
+    };
+    let stdout = read_pipe(child.stdout.take().map(|val| Box::new(val) as _));
+    let stderr = read_pipe(child.stderr.take().map(|val| Box::new(val) as _));
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(wait_error)? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(Error::command(
+                command,
+                format!(
+                    "did not finish within {}s (see --timeout)",
+                    timeout.as_secs()
+                ),
+            ));
+        }
+        thread::sleep(TIMEOUT_POLL);
+    };
+
+    Ok(process::Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+fn get_flake(
+    flake_url: &str,
+    options: &FlakeOptions,
+    nix_args: &[String],
+    all_systems: bool,
+    locked: &Option<String>,
+) -> Result<Flake, Error> {
+    let command = flake_show_command(flake_url, nix_args, all_systems);
+    let cache_path = locked
+        .as_ref()
+        .and_then(|locked| flake_cache_path(&command, locked));
+    if locked.is_none() {
+        debug(&format!(
+            "not caching {} since it has no clean revision",
+            flake_url
+        ));
+    }
+    let cached = cache_path
+        .as_ref()
+        .and_then(|path| fs::read_to_string(path).ok());
+
+    let raw_json = match cached {
+        Some(raw_json) => {
+            debug(&format!(
+                "using cached nix flake show output for {} from {}",
+                flake_url,
+                cache_path.as_ref().unwrap().display()
+            ));
+            raw_json
+        }
+        None => {
+            // Download hash data
+            debug(&format!("running nix flake show for {}", flake_url));
+            let out = run_command(&command)?;
+            if !out.status.success() {
+                return Err(Error::command(
+                    &command_line(&command),
+                    failure_message(&out),
+                ));
+            }
+
+            let raw_json = String::from_utf8_lossy(&out.stdout).to_string();
+            // A cache that can't be written only makes the next run slower, so it doesn't stop this one
+            if let Some(path) = &cache_path
+                && let Err(err) = write_cache(path, &raw_json)
+            {
+                debug(&format!("unable to cache {}: {}", path.display(), err));
+            }
+            raw_json
+        }
+    };
+
+    // Proccess into packages type
+    parse_flake(&raw_json, flake_url, options)
+}
+
+/// Where nix flake show output is cached by default, none if there is no home to put it in
+fn default_cache_dir() -> Option<PathBuf> {
+    env::var("XDG_CACHE_HOME")
+        .ok()
+        .filter(|val| !val.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            env::var("HOME")
+                .ok()
+                .filter(|val| !val.is_empty())
+                .map(|home| PathBuf::from(home).join(".cache"))
+        })
+        .map(|dir| dir.join("nix-update-report"))
+}
+
+/// The cache file for the output of a nix flake show command, none if caching is off. Only flakes with a clean locked revision are cached, so a moved ref or an edited tree always runs nix again. The file is named after the revision and a hash of the command, which covers the url, nix args, and systems.
+fn flake_cache_path(command: &[String], revision: &str) -> Option<PathBuf> {
+    let dir = CACHE_DIR.get()?.as_ref()?;
+    // Revisions are hex, but anything else is kept out of the file name
+    let safe_revision: String = revision
+        .chars()
+        .filter(|val| val.is_ascii_alphanumeric())
+        .take(40)
+        .collect();
+    Some(dir.join(format!(
+        "flake-show-{}-{:016x}.json",
+        safe_revision,
+        fnv1a(command.join("\0").as_bytes())
+    )))
+}
+
+/// A 64 bit FNV-1a hash, used for cache file names since it never changes between Rust releases like the std hashers can
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Saves output to the cache through a temporary file, so a run stopped midway never leaves a half written entry
+fn write_cache(path: &Path, contents: &str) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let temp = path.with_extension("json.tmp");
+    fs::write(&temp, contents)?;
+    fs::rename(&temp, path)
+}
+
+/// How many commits to ask Github for in each page of the compare api (the most it allows)
+const COMPARE_PAGE_SIZE: usize = 100;
+
+/// Ranges with more commits than this take a lot of requests, so warn about them
+const LARGE_RANGE_COMMITS: u64 = 5000;
+
+/// Never wait longer than this (in seconds) for a rate limit to clear
+const MAX_RETRY_WAIT: u64 = 300;
+
+/// Why a compare request failed
+enum FetchError {
+    /// Github is rate limiting requests, holds how long it asked to wait (in seconds) if it said
+    RateLimited(Option<u64>),
+    /// Any other failure
+    Failed(Error),
+}
+
+/// A http response split into its parts
+struct HttpResponse {
+    status: u16,
+    /// Header names are lowercase
+    headers: HashMap<String, String>,
+    body: String,
+}
+
+/// Splits a raw response with headers included (curl --dump-header - or gh api --include). Only the last header block is kept since redirects add earlier ones.
+fn parse_http_response(raw: &str) -> Option<HttpResponse> {
+    let mut rest = raw;
+    let mut response: Option<HttpResponse> = None;
+    while rest.starts_with("HTTP/") {
+        let (block, body) = rest
+            .split_once("\r\n\r\n")
+            .or_else(|| rest.split_once("\n\n"))
+            .unwrap_or((rest, ""));
+
+        let mut lines = block.lines();
+        let status = lines
+            .next()?
+            .split_whitespace()
+            .nth(1)?
+            .parse::<u16>()
+            .ok()?;
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+            .collect();
+
+        response = Some(HttpResponse {
+            status,
+            headers,
+            body: body.to_string(),
+        });
+        rest = body;
+    }
+
+    response
+}
+
+/// Turns a Github api response into its body, or the reason it failed. The request is shown in the error.
+fn check_response(response: HttpResponse, request: &str) -> Result<String, FetchError> {
+    if response.status == 200 {
+        return Ok(response.body);
+    }
+
+    // 403 is also used for missing permissions, so only count it when a rate limit header says so
+    let header = |name: &str| {
+        response
+            .headers
+            .get(name)
+            .and_then(|val| val.parse::<u64>().ok())
+    };
+    let rate_limited = response.status == 429
+        || (response.status == 403
+            && (header("retry-after").is_some() || header("x-ratelimit-remaining") == Some(0)));
+    if rate_limited {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |val| val.as_secs());
+        return Err(FetchError::RateLimited(header("retry-after").or_else(
+            || header("x-ratelimit-reset").map(|reset| reset.saturating_sub(now)),
+        )));
+    }
+
+    let message = serde_json::from_str::<Value>(&response.body)
+        .ok()
+        .and_then(|val| val["message"].as_str().map(|msg| msg.to_string()))
+        .unwrap_or(response.body.clone());
+    Err(FetchError::Failed(Error::command(
+        request,
+        format!("Github API responded with {}: {}", response.status, message),
+    )))
+}
+
+/// Fetch a Github api path (ex. repos/NixOS/nixpkgs/commits/master) using the gh cli
+fn fetch_gh(path: &str) -> Result<String, FetchError> {
+    let command = gh_api_command(path);
+    let line = command_line(&command);
+    let out = run_command(&command).map_err(FetchError::Failed)?;
+
+    // gh still prints the response when the request fails, only fall back to stderr when there is none
+    match parse_http_response(&String::from_utf8_lossy(&out.stdout)) {
+        Some(response) => check_response(response, &line),
+        None => Err(FetchError::Failed(Error::command(
+            &line,
+            failure_message(&out),
+        ))),
+    }
+}
+
+/// The command (program first) gh is run with to fetch a Github api path
+fn gh_api_command(path: &str) -> Vec<String> {
+    vec![
+        gh_bin().into(),
+        "api".into(),
+        "--include".into(),
+        path.into(),
+    ]
+}
+
+/// The Github api path of a page of the compare json
+fn compare_path(repo: &str, base_hash: &str, head_hash: &str, page: usize) -> String {
+    format!(
+        "repos/{}/compare/{}...{}?per_page={}&page={}",
+        repo, base_hash, head_hash, COMPARE_PAGE_SIZE, page
+    )
+}
+
+/// The Github api path of the commit a branch, tag, or hash points to
+fn commit_path(repo: &str, git_ref: &str) -> String {
+    format!("repos/{}/commits/{}", repo, git_ref)
+}
+
+/// The url of a Github api path in the REST api
+fn api_url(path: &str) -> String {
+    format!("https://api.github.com/{}", path)
+}
+
+/// The headers every REST api request sends (the token is added separately so it is never printed)
+const REST_HEADERS: [&str; 2] = [
+    "Accept: application/vnd.github+json",
+    "User-Agent: nix-update-report",
+];
+
+/// The curl arguments shared by REST api requests, before the headers and url
+const CURL_ARGS: [&str; 3] = ["--silent", "--show-error", "--location"];
+
+/// A curl command equivalent to a REST api request that can be pasted into a shell. The headers are passed as flags and the token is left as $GITHUB_TOKEN.
+fn rest_command_line(url: &str) -> String {
+    let mut command: Vec<String> = std::iter::once(curl_bin())
+        .chain(CURL_ARGS)
+        .map(|val| val.into())
+        .collect();
+    for header in REST_HEADERS {
+        command.extend(["-H".into(), header.into()]);
+    }
+
+    let mut line = command_line(&command);
+    if env::var("GITHUB_TOKEN").is_ok_and(|token| !token.is_empty()) {
+        line.push_str(" -H \"Authorization: Bearer $GITHUB_TOKEN\"");
+    }
+    line.push_str(&format!(" {}", command_line(&[url.into()])));
+    line
+}
+
+/// Fetch a Github api path by calling the REST api directly, authenticating with GITHUB_TOKEN if set
+fn fetch_rest(path: &str) -> Result<String, FetchError> {
+    let url = api_url(path);
+    debug(&format!("fetching {}", url));
+
+    // Headers are passed through a curl config on stdin so the token never shows up in the process list
+    let mut config: String = REST_HEADERS
+        .iter()
+        .map(|val| format!("header = \"{}\"\n", val))
+        .collect();
+    if let Ok(token) = env::var("GITHUB_TOKEN")
+        && !token.is_empty()
+    {
+        config.push_str(&format!("header = \"Authorization: Bearer {}\"\n", token));
+    }
+
+    let line = rest_command_line(&url);
+    let failed = |message: String| FetchError::Failed(Error::command(&line, message));
+    let mut child = Command::new(curl_bin())
+        .args(CURL_ARGS)
+        .args(["--config", "-", "--dump-header", "-", &url])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| failed(format!("unable to run: {}", err)))?;
+
+    child
+        .stdin
+        .take()
         .unwrap()
-        .as_array()
+        .write_all(config.as_bytes())
+        .map_err(|err| failed(format!("unable to pass the request headers: {}", err)))?;
+
+    let out = wait_for_output(child, &line).map_err(FetchError::Failed)?;
+    if !out.status.success() {
+        return Err(failed(failure_message(&out)));
+    }
+
+    match parse_http_response(&String::from_utf8_lossy(&out.stdout)) {
+        Some(response) => check_response(response, &line),
+        None => Err(failed("Unable to read the response from Github".into())),
+    }
+}
+
+/// Posts a json payload to a webhook with curl. The webhook url is its secret, so it is passed through a curl config on stdin and left out of messages.
+fn post_webhook(url: &str, payload: &str) -> Result<(), Error> {
+    debug("posting the report to the webhook");
+    // Config strings are quoted, so backslashes and quotes need escaping
+    let quote = |val: &str| format!("\"{}\"", val.replace('\\', "\\\\").replace('"', "\\\""));
+    let config = format!(
+        "url = {}\nheader = \"Content-Type: application/json\"\ndata-binary = {}\n",
+        quote(url),
+        quote(payload)
+    );
+
+    let line = format!("{} --config - (webhook url and payload)", curl_bin());
+    let failed = |message: String| Error::command(&line, message);
+    let mut child = Command::new(curl_bin())
+        .args(CURL_ARGS)
+        .args(["--fail", "--config", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| failed(format!("unable to run: {}", err)))?;
+
+    child
+        .stdin
+        .take()
         .unwrap()
-        .iter()
-        .map(|commit| {
-            commit
-                .get("commit")
-                .unwrap()
-                .get("message")
-                .unwrap()
-                .as_str()
-                .unwrap()
-                .to_string()
+        .write_all(config.as_bytes())
+        .map_err(|err| failed(format!("unable to pass the webhook request: {}", err)))?;
+
+    let out = wait_for_output(child, &line)?;
+    match out.status.success() {
+        true => Ok(()),
+        false => Err(failed(failure_message(&out))),
+    }
+}
+
+/// Fetch a Github api path with the backend, retrying with exponential backoff while rate limited. What names the request in the rate limit message.
+fn fetch_api(path: &str, what: &str, backend: &Backend, retries: u32) -> Result<String, Error> {
+    let mut attempt = 0;
+    loop {
+        let result = match backend {
+            Backend::Rest => fetch_rest(path),
+            Backend::Gh => {
+                debug(&format!("fetching {} with gh", path));
+                fetch_gh(path)
+            }
+            Backend::Git => unreachable!("the git backend reads commits locally"),
+        };
+
+        match result {
+            Ok(body) => return Ok(body),
+            Err(FetchError::Failed(err)) => return Err(err),
+            Err(FetchError::RateLimited(wait)) => {
+                // Use the wait Github asked for, falling back to 1s, 2s, 4s, ...
+                let wait = wait.unwrap_or(1 << attempt.min(8)).max(1);
+                if attempt >= retries || wait > MAX_RETRY_WAIT {
+                    return Err(Error::command(
+                        &api_url(path),
+                        format!(
+                            "Github API rate limit hit for {}, gave up after {} retries (it asked to wait {}s). Set GITHUB_TOKEN or try again later.",
+                            what, attempt, wait
+                        ),
+                    ));
+                }
+
+                attempt += 1;
+                eprintln!(
+                    "Github API rate limit hit, retrying in {}s ({}/{})...",
+                    wait, attempt, retries
+                );
+                thread::sleep(Duration::from_secs(wait));
+            }
+        }
+    }
+}
+
+/// Fetch a page of the compare json
+fn fetch_compare(
+    repo: &str,
+    base_hash: &str,
+    head_hash: &str,
+    page: usize,
+    backend: &Backend,
+    retries: u32,
+) -> Result<String, Error> {
+    debug(&format!(
+        "fetching page {} of [{}...{}]",
+        page, base_hash, head_hash
+    ));
+    fetch_api(
+        &compare_path(repo, base_hash, head_hash, page),
+        &format!("[{}...{}]", base_hash, head_hash),
+        backend,
+        retries,
+    )
+}
+
+/// Fetch the hash of the commit a branch or tag points to (ex. the latest commit on a branch)
+fn fetch_commit_hash(
+    repo: &str,
+    git_ref: &str,
+    backend: &Backend,
+    retries: u32,
+) -> Result<String, Error> {
+    let path = commit_path(repo, git_ref);
+    let body = fetch_api(&path, git_ref, backend, retries)?;
+    serde_json::from_str::<Value>(&body)
+        .ok()
+        .and_then(|val| val["sha"].as_str().map(|sha| sha.to_string()))
+        .ok_or(Error::parse(
+            &api_url(&path),
+            "the response has no commit hash",
+        ))
+}
+
+fn get_nixpkgs(
+    repo: &str,
+    base_hash: &str,
+    head_hash: &str,
+    backend: &Backend,
+    retries: u32,
+) -> Result<Nixpkgs, Error> {
+    // Github only returns part of the commits for each request, so page through until all of them are seen
+    let mut commits: Vec<CommitDetails> = vec![];
+    let mut page = 1;
+    loop {
+        // Download hash data
+        let raw_json = fetch_compare(repo, base_hash, head_hash, page, backend, retries)?;
+
+        // Proccess into json
+        let what = api_url(&compare_path(repo, base_hash, head_hash, page));
+        let full_json: Value =
+            serde_json::from_str(raw_json.as_str()).map_err(|err| Error::parse(&what, err))?;
+
+        let total_commits = full_json["total_commits"].as_u64().unwrap_or(0);
+        if page == 1 && total_commits > LARGE_RANGE_COMMITS {
+            eprintln!(
+                "Warning: [{}...{}] spans {} commits, this will take {} requests to download",
+                base_hash,
+                head_hash,
+                total_commits,
+                total_commits.div_ceil(COMPARE_PAGE_SIZE as u64)
+            );
+        }
+
+        let page_commits = get_compare_commits(&full_json, &what)?;
+        let page_len = page_commits.len();
+        commits.extend(page_commits);
+
+        if page_len == 0 || commits.len() as u64 >= total_commits {
+            break;
+        }
+        page += 1;
+    }
+
+    Ok(Nixpkgs::new_with_details(&commits))
+}
+
+/// Pulls the commits out of the Github compare json, failing with a clear message if it doesn't have the expected shape
+fn get_compare_commits(full_json: &Value, what: &str) -> Result<Vec<CommitDetails>, Error> {
+    CommitDetails::from_compare(full_json).map_err(|err| Error::parse(what, err))
+}
+
+/// The command (program first) git is run with to list the commits between two refs
+fn git_log_command(repo_path: &str, base_hash: &str, head_hash: &str) -> Vec<String> {
+    // Fields are split by the unit separator and commits by the record separator so full messages can hold anything
+    [
+        "git",
+        "-C",
+        repo_path,
+        "log",
+        "--reverse",
+        "--pretty=format:%H%x1f%aI%x1f%B%x1e",
+        &format!("{}..{}", base_hash, head_hash),
+    ]
+    .iter()
+    .map(|val| val.to_string())
+    .collect()
+}
+
+/// The command (program first) git is run with to find the hash of the commit a ref points to
+fn git_rev_parse_command(repo_path: &str, git_ref: &str) -> Vec<String> {
+    [
+        "git",
+        "-C",
+        repo_path,
+        "rev-parse",
+        "--verify",
+        "--end-of-options",
+        &format!("{}^{{commit}}", git_ref),
+    ]
+    .iter()
+    .map(|val| val.to_string())
+    .collect()
+}
+
+/// Checks if a ref already is a commit hash (7 to 40 hex characters), so there is nothing to resolve
+fn is_hash(git_ref: &str) -> bool {
+    (7..=40).contains(&git_ref.len()) && git_ref.chars().all(|val| val.is_ascii_hexdigit())
+}
+
+/// Resolves a branch or tag to the hash of the commit it points to, so a report records exactly what was compared even if the ref moves later. Hashes are kept as they are. Returns the hash and the ref it was resolved from, if any.
+fn resolve_ref(
+    git_ref: &str,
+    repo: &str,
+    repo_path: &str,
+    backend: &Backend,
+    retries: u32,
+) -> Result<(String, Option<String>), Error> {
+    if is_hash(git_ref) {
+        return Ok((git_ref.into(), None));
+    }
+
+    let hash = match backend {
+        Backend::Git => {
+            let command = git_rev_parse_command(repo_path, git_ref);
+            let out = run_command(&command)?;
+            if !out.status.success() {
+                return Err(Error::command(
+                    &command_line(&command),
+                    failure_message(&out),
+                ));
+            }
+            String::from_utf8_lossy(&out.stdout).trim().to_string()
+        }
+        _ => fetch_commit_hash(repo, git_ref, backend, retries)?,
+    };
+    debug(&format!("resolved {} to {}", git_ref, hash));
+    Ok((hash, Some(git_ref.into())))
+}
+
+/// Reads the commits between two refs from a local clone, oldest first
+fn get_git_nixpkgs(repo_path: &str, base_hash: &str, head_hash: &str) -> Result<Nixpkgs, Error> {
+    debug(&format!(
+        "reading [{}..{}] from git in {}",
+        base_hash, head_hash, repo_path
+    ));
+    let command = git_log_command(repo_path, base_hash, head_hash);
+    let out = run_command(&command)?;
+    if !out.status.success() {
+        return Err(Error::command(
+            &command_line(&command),
+            failure_message(&out),
+        ));
+    }
+
+    let commits: Vec<CommitDetails> = String::from_utf8_lossy(&out.stdout)
+        .split('\u{1e}')
+        .filter_map(|record| {
+            let mut fields = record.trim_start_matches('\n').splitn(3, '\u{1f}');
+            let (sha, date, message) = (fields.next()?, fields.next()?, fields.next()?);
+            Some(CommitDetails {
+                message: message.trim_end().into(),
+                date: Some(date.into()),
+                sha: Some(sha.into()),
+            })
         })
         .collect();
 
-    Nixpkgs::new(&commits)
+    Ok(Nixpkgs::new_with_details(&commits))
+}
+
+/// Reads commits from a file (or stdin when the path is -). The file can hold either the raw compare json or one commit message per line.
+fn read_nixpkgs(path: &str) -> Result<Nixpkgs, Error> {
+    let mut contents = String::new();
+    if path == "-" {
+        io::stdin()
+            .read_to_string(&mut contents)
+            .map_err(|err| Error::io(path, err))?;
+    } else {
+        contents = fs::read_to_string(path).map_err(|err| Error::io(path, err))?;
+    }
+
+    if let Ok(full_json) = serde_json::from_str::<Value>(contents.as_str())
+        && full_json.get("commits").is_some()
+    {
+        return Ok(Nixpkgs::new_with_details(&get_compare_commits(
+            &full_json, path,
+        )?));
+    }
+
+    let commits: Vec<String> = contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.to_string())
+        .collect();
+
+    Ok(Nixpkgs::new(&commits))
+}
+
+/// Reads a flake.lock file, failing with a clear message if it doesn't have the expected shape
+fn read_lock(path: &str) -> Result<FlakeLock, Error> {
+    let raw_json = fs::read_to_string(path).map_err(|err| Error::io(path, err))?;
+    let lock_json: Value =
+        serde_json::from_str(raw_json.as_str()).map_err(|err| Error::parse(path, err))?;
+
+    FlakeLock::new(&lock_json).map_err(|err| Error::parse(path, err))
+}
+
+/// Reads a json report saved from the nixpkgs command, failing with a clear message if it can't be read back
+fn read_report(path: &str) -> Result<NixpkgsReport, Error> {
+    let raw_json = fs::read_to_string(path).map_err(|err| Error::io(path, err))?;
+
+    NixpkgsReport::from_json(&raw_json).map_err(|err| Error::parse(path, err))
+}
+
+/// Reads a report template file
+fn read_template(path: &str) -> Result<String, Error> {
+    fs::read_to_string(path).map_err(|err| Error::io(path, err))
+}
+
+/// Prints a debug log message when --verbose is given
+fn debug(message: &str) {
+    log::log(log::Level::Debug, message);
+}
+
+/// The nix executable set by --nix-bin or NIX_BIN
+static NIX_BIN: OnceLock<String> = OnceLock::new();
+
+/// The gh executable set by --gh-bin or GH_BIN
+static GH_BIN: OnceLock<String> = OnceLock::new();
+
+/// The curl executable set by --curl-bin or CURL_BIN
+static CURL_BIN: OnceLock<String> = OnceLock::new();
+
+/// Grab the nix executable to run, nix from the PATH unless overridden
+fn nix_bin() -> &'static str {
+    NIX_BIN.get().map_or("nix", |val| val.as_str())
+}
+
+/// Grab the gh executable to run, gh from the PATH unless overridden
+fn gh_bin() -> &'static str {
+    GH_BIN.get().map_or("gh", |val| val.as_str())
+}
+
+/// Grab the curl executable to run, curl from the PATH unless overridden
+fn curl_bin() -> &'static str {
+    CURL_BIN.get().map_or("curl", |val| val.as_str())
+}
+
+/// The directory flake show output is cached in, none when caching is off (set by --cache-dir and --no-cache)
+static CACHE_DIR: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+/// How long a nix, gh, git, or curl command may run before it is killed, none waits forever (set by --timeout)
+static TIMEOUT: OnceLock<Option<Duration>> = OnceLock::new();
+
+/// Set by --quiet to silence progress messages
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Prints a progress message. Goes to stderr when the report is written to stdout so it doesn't end up in the report.
+fn progress(out: &str, message: &str) {
+    if QUIET.load(Ordering::Relaxed) {
+        return;
+    }
+
+    if out == "-" {
+        eprintln!("{}", message);
+    } else {
+        println!("{}", message);
+    }
+}
+
+/// Set by --append to add reports to the out file instead of overwriting it
+static APPEND: AtomicBool = AtomicBool::new(false);
+
+/// The line --append puts new reports after, so anything above it stays on top (ex. a changelog header)
+const APPEND_MARKER: &str = "<!-- nix-update-report -->";
+
+/// Puts a new report ahead of the existing ones, right after the marker line if there is one
+fn insert_report(existing: &str, report: &str) -> String {
+    let split = existing.find(APPEND_MARKER).map_or(0, |start| {
+        existing[start..]
+            .find('\n')
+            .map_or(existing.len(), |end| start + end + 1)
+    });
+    let (before, after) = existing.split_at(split);
+
+    // Keep the marker on its own line even if the file didn't end with a newline
+    let separator = match before.is_empty() || before.ends_with('\n') {
+        true => "",
+        false => "\n",
+    };
+    format!("{}{}{}\n{}", before, separator, report, after)
+}
+
+/// Writes the report to the out path, or to stdout when out is -
+fn write_report(out: &str, report: &str) -> Result<(), Error> {
+    save_report(out, report, APPEND.load(Ordering::Relaxed))
+}
+
+/// Writes the report to the out path (or stdout when out is -), adding it to the existing reports if append is set
+fn save_report(out: &str, report: &str, append: bool) -> Result<(), Error> {
+    if out == "-" {
+        let mut output = io::stdout().lock();
+        return output
+            .write_all(report.as_bytes())
+            .and_then(|_| output.flush())
+            .map_err(|err| Error::io(out, err));
+    }
+
+    // The report is only copied when it has to be joined with the existing ones
+    match append {
+        true => match fs::read_to_string(out) {
+            Ok(existing) => fs::write(out, insert_report(&existing, report)),
+            // Nothing to add to yet, so write it like normal
+            Err(err) if err.kind() == io::ErrorKind::NotFound => fs::write(out, report),
+            Err(err) => return Err(Error::io(out, err)),
+        },
+        false => fs::write(out, report),
+    }
+    .map_err(|err| Error::io(out, err))
+}
+
+/// Reads the last seen commit hash of a watch, none if nothing has been seen yet
+fn read_watch_state(path: &str) -> Result<Option<String>, Error> {
+    match fs::read_to_string(path) {
+        Ok(state) => Ok(Some(state.trim().to_string()).filter(|val| !val.is_empty())),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(Error::io(path, err)),
+    }
+}
+
+/// Replaces a file by writing a temporary file next to it and renaming it over, so stopping midway never leaves it half written
+fn write_atomic(path: &str, contents: &str) -> Result<(), Error> {
+    let temp = temp_path(path);
+    fs::write(&temp, contents)
+        .and_then(|_| fs::rename(&temp, path))
+        .map_err(|err| Error::io(path, err))
+}
+
+/// The temp file write_atomic writes before renaming it over the path
+fn temp_path(path: &str) -> String {
+    format!("{}.tmp", path)
+}
+
+/// Removes the temp file write_atomic leaves behind when it is stopped between the write and the rename
+fn remove_stale_temp(path: &str) -> Result<(), Error> {
+    let temp = temp_path(path);
+    match fs::remove_file(&temp) {
+        Ok(()) => {
+            debug(&format!("removed {} left by an earlier watch", temp));
+            Ok(())
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(Error::io(&temp, err)),
+    }
+}
+
+/// Adds a report to the top of the out file for a watch. Stdout just gets the report printed.
+fn append_watch_report(out: &str, report: &str) -> Result<(), Error> {
+    if out == "-" {
+        return save_report(out, report, true);
+    }
+
+    let existing = match fs::read_to_string(out) {
+        Ok(existing) => existing,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => "".into(),
+        Err(err) => return Err(Error::io(out, err)),
+    };
+    let report = match existing.is_empty() {
+        true => report.to_string(),
+        false => insert_report(&existing, report),
+    };
+    write_atomic(out, &report)
+}
+
+/// Prints an error under a heading saying what kind of failure it was. Failed checks are printed as they are since the report was still made.
+fn print_error(err: &Error) {
+    let heading = match err {
+        Error::Parse { .. } => Some("Parse Error:"),
+        Error::Command { .. } => Some("Command Error:"),
+        Error::Io { .. } => Some("File Error:"),
+        Error::Invalid(_) => Some("Invalid Input:"),
+        Error::Check(_) => None,
+    };
+    if let Some(heading) = heading {
+        eprintln!("{}", heading);
+    }
+    eprintln!("{}", err);
 }
 
-fn main() {
+fn main() -> ExitCode {
     // Parse args
     let args = Cli::parse();
+    QUIET.store(args.quiet, Ordering::Relaxed);
+    APPEND.store(args.append, Ordering::Relaxed);
+    log::set_verbosity(args.verbose);
+    NIX_BIN.get_or_init(|| args.nix_bin.clone());
+    GH_BIN.get_or_init(|| args.gh_bin.clone());
+    CURL_BIN.get_or_init(|| args.curl_bin.clone());
+    TIMEOUT.get_or_init(|| args.timeout.map(Duration::from_secs));
+    CACHE_DIR.get_or_init(|| match args.no_cache {
+        true => None,
+        false => args
+            .cache_dir
+            .as_ref()
+            .map(PathBuf::from)
+            .or_else(default_cache_dir),
+    });
+
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            print_error(&err);
+            ExitCode::FAILURE
+        }
+    }
+}
 
+/// Runs the command picked on the command line
+fn run(args: &Cli) -> Result<(), Error> {
     match &args.command {
         Some(Commands::Flake {
             previous_url,
             next_url,
+            previous_file,
+            next_file,
+            refs,
+            nix_args,
             title,
             out,
+            format,
+            release_version,
+            table,
+            by_package,
+            dedup_archs,
+            toc,
+            matrix,
+            limit,
+            sort_by,
+            filter,
+            exclude,
+            show_descriptions,
+            show_description_changes,
+            max_description_len,
+            include_unchanged,
+            detect_renames,
+            mappings,
+            map_file,
+            check_coverage,
+            min_archs,
+            stat,
+            template,
+            legacy_output,
+            output,
+            archs,
+            current_system,
+            print_command,
+            webhook,
+            gates,
         }) => {
+            let flake_options = FlakeOptions {
+                legacy_packages: *legacy_output,
+                output: output.flake_output(),
+            };
+            // Read the mappings up front so a bad mapping file fails before anything is downloaded
+            let name_map: HashMap<String, String> = mappings
+                .iter()
+                .cloned()
+                .chain(match map_file {
+                    Some(path) => read_mappings(path)?,
+                    None => vec![],
+                })
+                .collect();
+            // Two packages mapped onto one name would both be compared against it
+            let mut new_names: Vec<&String> = name_map.values().collect();
+            new_names.sort();
+            if let Some(pair) = new_names.windows(2).find(|pair| pair[0] == pair[1]) {
+                return Err(Error::Invalid(format!(
+                    "More than one package is mapped to {}",
+                    pair[0]
+                )));
+            }
+            // Html lists every arch in collapsed sections, so the markdown only layouts can't be used with it
+            if template.is_none() && *format == FlakeReportFormat::Html {
+                let markdown_only = [
+                    ("--table", *table),
+                    ("--by-package", *by_package),
+                    ("--matrix", *matrix),
+                    ("--toc", *toc),
+                ];
+                if let Some((flag, _)) = markdown_only.iter().find(|(_, set)| *set) {
+                    return Err(Error::Invalid(format!(
+                        "{} only changes markdown reports, it can't be used with --format html",
+                        flag
+                    )));
+                }
+            }
+            let (previous_url, next_url) = match refs.is_empty() {
+                true => (previous_url.clone(), next_url.clone()),
+                false => get_ref_urls(previous_url, next_url, refs)?,
+            };
+            let (prev_source, next_source) =
+                get_flake_sources(&previous_url, &next_url, previous_file, next_file)?;
+            if *print_command {
+                for source in [&prev_source, &next_source] {
+                    if let FlakeSource::Url(flake_url) = source {
+                        println!(
+                            "{}",
+                            command_line(&flake_show_command(
+                                flake_url,
+                                nix_args,
+                                !*current_system
+                            ))
+                        );
+                        println!(
+                            "{}",
+                            command_line(&flake_metadata_command(flake_url, nix_args))
+                        );
+                    }
+                }
+                return Ok(());
+            }
+
             // Grab commit data
-            println!("Downloading and parsing packages based on hashes...");
-            let prev_packages = get_flake(previous_url);
-            let next_packages = get_flake(next_url);
+            progress(out, "Downloading and parsing packages based on hashes...");
+            let (prev_result, next_result) = thread::scope(|scope| {
+                // Both downloads are independent so run them at the same time
+                let prev_handle = scope
+                    .spawn(|| load_flake(&prev_source, &flake_options, nix_args, !*current_system));
+                let next_handle = scope
+                    .spawn(|| load_flake(&next_source, &flake_options, nix_args, !*current_system));
+
+                let join = |handle: thread::ScopedJoinHandle<
+                    '_,
+                    Result<(Flake, Option<String>), Error>,
+                >| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|err| std::panic::resume_unwind(err))
+                };
+                (join(prev_handle), join(next_handle))
+            });
+            let ((mut prev_packages, prev_rev), (mut next_packages, next_rev)) =
+                (prev_result?, next_result?);
+
+            // Drop the archs that weren't asked for so they don't show up as added or removed
+            if !archs.is_empty() {
+                let prev_missing = prev_packages.retain_archs(archs);
+                let next_missing = next_packages.retain_archs(archs);
+                for arch in prev_missing.iter().filter(|val| next_missing.contains(val)) {
+                    eprintln!("Warning: neither flake has packages for {}", arch);
+                }
+            }
+
+            for (old_name, new_name) in prev_packages.taken_mappings(&name_map) {
+                eprintln!(
+                    "Warning: not mapping {} to {} since the previous flake already has {}",
+                    old_name, new_name, new_name
+                );
+            }
 
             // Grab compare data
-            println!("Comparing flakes or flake versions...");
-            let compare_data = FlakeCompareData::new(&prev_packages, &next_packages);
+            progress(out, "Comparing flakes or flake versions...");
+            let mut compare_data = FlakeCompareData::new_with_options(
+                &prev_packages,
+                &next_packages,
+                &FlakeCompareOptions {
+                    include_unchanged: *include_unchanged,
+                    detect_renames: *detect_renames,
+                    filter: NameFilter {
+                        include: filter.clone(),
+                        exclude: exclude.clone(),
+                    },
+                    name_map,
+                    check_coverage: *check_coverage,
+                    min_archs: *min_archs,
+                },
+            );
+            compare_data.set_revisions(prev_rev, next_rev);
 
             // Generate report and save to report.md
-            println!("Writing report...");
-            let mut output = File::create(out).unwrap();
-            write!(output, "{}", compare_data.generate_report(title))
-                .expect(format!("Unable to write {}", out).as_str());
+            progress(out, "Writing report...");
+            let report_options = FlakeReportOptions {
+                table: *table,
+                show_descriptions: *show_descriptions,
+                show_description_changes: *show_description_changes,
+                max_description_len: *max_description_len,
+                limit: *limit,
+                by_package: *by_package,
+                dedup_archs: *dedup_archs,
+                update_order: sort_by.update_order(),
+                toc: *toc,
+                matrix: *matrix,
+            };
+            let report = match template {
+                Some(path) => template::render(
+                    &read_template(path)?,
+                    &compare_data.template_context(title, &report_options),
+                ),
+                None => match format {
+                    FlakeReportFormat::Markdown => {
+                        compare_data.generate_report_with_options(title, &report_options)
+                    }
+                    FlakeReportFormat::Html => {
+                        compare_data.generate_html_report(title, &report_options)
+                    }
+                    FlakeReportFormat::Changelog => {
+                        compare_data.generate_changelog(release_version)
+                    }
+                    FlakeReportFormat::Webhook => {
+                        compare_data.generate_webhook(title, webhook.style())
+                    }
+                },
+            };
+            match (template, format) {
+                (None, FlakeReportFormat::Webhook) => webhook.send(&report)?,
+                _ => write_report(out, &report)?,
+            }
+            if *stat {
+                println!("{}", compare_data.stat_line());
+            }
+
+            gates.annotate(
+                &compare_data.removal_lines(),
+                &compare_data.downgrade_lines(),
+            );
+            gates.check(compare_data.has_removals(), compare_data.has_downgrades())?;
         }
         Some(Commands::Nixpkgs {
             previous,
             next,
+            title,
             out,
+            format,
+            release_version,
+            backend,
+            repo,
+            repo_path,
+            commits_file,
+            group_by,
+            detect_renames,
+            link_packages,
+            link_commits,
+            security_keywords,
+            limit,
+            sort_by,
+            filter,
+            exclude,
+            stat,
+            template,
+            retries,
+            since,
+            until,
+            branch_tag,
+            skip_prefixes,
+            no_validate,
+            print_command,
+            webhook,
+            gates,
         }) => {
+            // Files are read directly so there is no command to print for them
+            if *print_command {
+                match (commits_file, backend) {
+                    (Some(_), _) => (),
+                    (None, Backend::Git) => {
+                        println!(
+                            "{}",
+                            command_line(&git_log_command(repo_path, previous, next))
+                        )
+                    }
+                    (None, Backend::Gh) => println!(
+                        "{}",
+                        command_line(&gh_api_command(&compare_path(repo, previous, next, 1)))
+                    ),
+                    (None, Backend::Rest) => println!(
+                        "{}",
+                        rest_command_line(&api_url(&compare_path(repo, previous, next, 1)))
+                    ),
+                }
+                return Ok(());
+            }
+
+            // Catch typos here since Github only answers them with a 404
+            if !*no_validate && commits_file.is_none() && *backend != Backend::Git {
+                for (side, git_ref) in [("previous", previous), ("next", next)] {
+                    if let Err(err) = validate_ref(git_ref) {
+                        return Err(Error::Invalid(format!(
+                            "Invalid {} ref '{}': {}\nPass --no-validate to use it anyway",
+                            side, git_ref, err
+                        )));
+                    }
+                }
+            }
+
+            // Pin branches and tags to hashes so both ends stay put while paging and the report can show them. Commit files have nothing to resolve against.
+            let ((base, base_ref), (head, head_ref)) = match commits_file {
+                Some(_) => ((previous.clone(), None), (next.clone(), None)),
+                None => {
+                    progress(out, "Resolving refs...");
+                    (
+                        resolve_ref(previous, repo, repo_path, backend, *retries)?,
+                        resolve_ref(next, repo, repo_path, backend, *retries)?,
+                    )
+                }
+            };
+
             // Grab commit data
-            println!("Downloading and parsing commits based on hashes...");
-            let npkgs = get_nixpkgs(previous, next);
+            let mut npkgs = match commits_file {
+                Some(path) => {
+                    progress(
+                        out,
+                        &format!("Reading and parsing commits from {}...", path),
+                    );
+                    read_nixpkgs(path)?
+                }
+                None if *backend == Backend::Git => {
+                    progress(
+                        out,
+                        &format!("Reading and parsing commits from {}...", repo_path),
+                    );
+                    get_git_nixpkgs(repo_path, &base, &head)?
+                }
+                None => {
+                    progress(out, "Downloading and parsing commits based on hashes...");
+                    get_nixpkgs(repo, &base, &head, backend, *retries)?
+                }
+            };
+
+            if let Some(prefixes) = skip_prefixes {
+                npkgs.set_skip_prefixes(prefixes);
+            }
+            if since.is_some() || until.is_some() {
+                npkgs.retain_dates(*since, *until);
+            }
+            if let Some(tag) = branch_tag {
+                npkgs.retain_tag(tag);
+            }
+            let name_filter = NameFilter {
+                include: filter.clone(),
+                exclude: exclude.clone(),
+            };
+            if name_filter.is_active() {
+                npkgs.retain_names(&name_filter);
+            }
+
+            progress(out, "Writing report...");
+            let mut report_data = npkgs.collect_report(repo, &base, &head);
+            report_data.base_ref = base_ref;
+            report_data.head_ref = head_ref;
+            report_data.filter = name_filter.describe();
+            if let Some(keywords) = security_keywords {
+                report_data.security = Some(npkgs.find_keywords(keywords));
+            }
+            if *detect_renames {
+                report_data.detect_renames();
+            }
+            let options = NixpkgsReportOptions {
+                group_by_set: *group_by == Some(GroupBy::Set),
+                link_packages: *link_packages,
+                link_commits: *link_commits,
+                limit: *limit,
+                title: title.clone(),
+                update_order: sort_by.update_order(),
+            };
+            let report = match (template, format) {
+                (Some(path), _) => template::render(
+                    &read_template(path)?,
+                    &report_data.template_context(&options),
+                ),
+                (None, ReportFormat::Markdown) => report_data.to_markdown(&options),
+                (None, ReportFormat::Json) => report_data.to_json(),
+                (None, ReportFormat::Html) => report_data.to_html(&options),
+                (None, ReportFormat::Changelog) => report_data.to_changelog(release_version),
+                (None, ReportFormat::Webhook) => report_data.to_webhook(&options, webhook.style()),
+            };
+
+            match (template, format) {
+                (None, ReportFormat::Webhook) => webhook.send(&report)?,
+                _ => write_report(out, &report)?,
+            }
+            if *stat {
+                println!("{}", report_data.stat_line());
+            }
+
+            gates.annotate(&report_data.removal_lines(), &report_data.downgrade_lines());
+            gates.check(report_data.has_removals(), report_data.has_downgrades())?;
+        }
+        Some(Commands::Metadata {
+            url,
+            file,
+            format,
+            out,
+            nix_args,
+            output,
+            legacy_output,
+            current_system,
+        }) => {
+            let options = FlakeOptions {
+                legacy_packages: *legacy_output,
+                output: output.flake_output(),
+            };
+            let source = match (url, file) {
+                (_, Some(path)) => FlakeSource::File(path.clone()),
+                (Some(flake_url), None) => FlakeSource::Url(flake_url.clone()),
+                (None, None) => unreachable!(),
+            };
+
+            progress(out, "Downloading and parsing packages...");
+            let (flake, revision) = load_flake(&source, &options, nix_args, !*current_system)?;
+            let inventory = match format {
+                InventoryFormat::Markdown => flake.inventory(&format!(
+                    "Packages in {}{}",
+                    url.as_ref().or(file.as_ref()).unwrap(),
+                    revision.map_or("".into(), |val| format!(" ({})", val))
+                )),
+                InventoryFormat::Json => flake.to_json(),
+            };
+            write_report(out, &inventory)?;
+        }
+        Some(Commands::Lock {
+            previous,
+            next,
+            title,
+            out,
+            fail_on_removed,
+        }) => {
+            progress(out, "Reading and comparing flake locks...");
+            let compare_data = FlakeLockCompareData::new(&read_lock(previous)?, &read_lock(next)?);
+
+            progress(out, "Writing report...");
+            write_report(out, &compare_data.generate_report(title))?;
 
-            println!("Writing report...");
-            let mut output = File::create(out).unwrap();
-            write!(output, "{}", npkgs.generate_report(previous, next))
-                .expect(format!("Unable to write {}", out).as_str());
+            if *fail_on_removed && compare_data.has_removals() {
+                return Err(Error::Check(vec!["Report contains removed inputs".into()]));
+            }
+        }
+        Some(Commands::DiffReports {
+            old,
+            new,
+            title,
+            out,
+            fail_on_removed,
+        }) => {
+            progress(out, "Reading and comparing reports...");
+            let diff = ReportDiff::new(&read_report(old)?, &read_report(new)?);
+
+            progress(out, "Writing report...");
+            write_report(out, &diff.generate_report(title))?;
+
+            if *fail_on_removed && diff.has_new_removals() {
+                return Err(Error::Check(vec![
+                    "Report contains newly removed packages".into(),
+                ]));
+            }
+        }
+        Some(Commands::Watch {
+            repo,
+            branch,
+            interval,
+            state_file,
+            title,
+            out,
+            backend,
+            retries,
+        }) => {
+            if *backend == Backend::Git {
+                return Err(Error::Invalid(
+                    "The watch command needs the rest or gh backend".into(),
+                ));
+            }
+
+            // Nothing is kept in memory that isn't also on disk, and both files are replaced whole, so Ctrl-C can stop the watch at any point. The state is only saved after the report, so the worst case is a range being reported twice. A stop between a write and its rename only leaves a temp file, which is cleared here.
+            for path in [state_file, out].into_iter().filter(|val| *val != "-") {
+                remove_stale_temp(path)?;
+            }
+            let mut last_seen = read_watch_state(state_file)?;
+            progress(
+                out,
+                &format!(
+                    "Watching {} on {} every {}s (stop with Ctrl-C)...",
+                    branch, repo, interval
+                ),
+            );
+            loop {
+                match fetch_commit_hash(repo, branch, backend, *retries) {
+                    Ok(head) if last_seen.as_ref() != Some(&head) => {
+                        let report = match &last_seen {
+                            Some(base) => {
+                                progress(
+                                    out,
+                                    &format!("{} moved to {}, writing report...", branch, head),
+                                );
+                                get_nixpkgs(repo, base, &head, backend, *retries).map(|npkgs| {
+                                    let mut report_data = npkgs.collect_report(repo, base, &head);
+                                    report_data.head_ref = Some(branch.clone());
+                                    Some(report_data.to_markdown(&NixpkgsReportOptions {
+                                        title: title.clone(),
+                                        ..Default::default()
+                                    }))
+                                })
+                            }
+                            // There is nothing to compare the first commit to, so just remember it
+                            None => {
+                                progress(out, &format!("{} is at {}", branch, head));
+                                Ok(None)
+                            }
+                        };
+
+                        match report {
+                            Ok(report) => {
+                                if let Some(report) = report {
+                                    append_watch_report(out, &report)?;
+                                }
+                                write_atomic(state_file, &format!("{}\n", head))?;
+                                last_seen = Some(head);
+                            }
+                            // The state didn't move, so the next check tries the same range again
+                            Err(err) => eprintln!("Warning: unable to compare {}: {}", branch, err),
+                        }
+                    }
+                    Ok(_) => debug(&format!("{} has not moved", branch)),
+                    // Keep watching through network hiccups, the next check will catch up
+                    Err(err) => eprintln!("Warning: unable to check {}: {}", branch, err),
+                }
+                thread::sleep(Duration::from_secs(*interval));
+            }
+        }
+        Some(Commands::Completions { shell }) => {
+            print!("{}", completions::generate(*shell, Cli::command()));
         }
         _ => (),
     }
+
+    Ok(())
 }
@@ -1,131 +1,1294 @@
 // Structs used for processing nix commit data
 
-use std::collections::HashSet;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    error, fmt,
+    sync::LazyLock,
+};
 
+use crate::html;
+use crate::log::{debug, trace};
+use crate::packages::{
+    BumpLevel, NameFilter, PkgVersion, UpdateOrder, VersionDirection, pair_renames,
+};
+use crate::template::{self, TemplateContext, limit_list, net_change};
+use crate::webhook::{self, WebhookStyle};
 use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use time::{Date, OffsetDateTime, format_description::well_known::Rfc3339};
+
+/// Matches commit messages in the `name: action` form nixpkgs uses. There can be several comma separated names and version changes (ex. `foo,bar: 1.0 -> 2.0, 3.0 -> 4.0`) and a leading branch tag (ex. `[24.05]`).
+static COMMIT_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^(?:\[(?<tag>[^\]]+)\] )?(?<names>\S+(?:, \S+)*): (?<action>drop|init|(?:[A-Za-z0-9-.:]+ -> [A-Za-z0-9-.:]+(?:, [A-Za-z0-9-.:]+ -> [A-Za-z0-9-.:]+)*))",
+    )
+    .unwrap()
+});
+
+/// The subject prefixes of commits that don't change a package (merges, tree-wide changes, and maintainer list edits), used unless Nixpkgs::set_skip_prefixes is given others
+pub const DEFAULT_SKIP_PREFIXES: [&str; 3] = ["Merge", "treewide", "maintainers:"];
+
+/// A package attribute path (ex. python3Packages.requests), with its top-level package set split out
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Debug, Serialize, Deserialize)]
+#[serde(into = "String", from = "String")]
+pub struct PkgAttr {
+    /// The full attribute path
+    pub name: String,
+    /// The package set the attribute lives in, none for top-level packages
+    pub set: Option<String>,
+}
+
+impl fmt::Display for PkgAttr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+impl PkgAttr {
+    /// Link to the package on search.nixos.org
+    pub fn search_url(&self) -> String {
+        // Everything but the unreserved characters gets percent encoded
+        let query: String = self
+            .name
+            .bytes()
+            .map(|byte| match byte {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                    (byte as char).to_string()
+                }
+                _ => format!("%{:02X}", byte),
+            })
+            .collect();
+
+        format!("https://search.nixos.org/packages?query={}", query)
+    }
+}
+
+impl From<PkgAttr> for String {
+    fn from(attr: PkgAttr) -> String {
+        attr.name
+    }
+}
+
+impl From<String> for PkgAttr {
+    /// Splits the package set back out of a full attribute path (the same way commit messages are read)
+    fn from(name: String) -> PkgAttr {
+        PkgAttr {
+            set: name.split_once('.').map(|(set, _)| set.into()),
+            name,
+        }
+    }
+}
 
 /// Holds the data for a single nix commit
 #[derive(PartialEq, Eq, Clone, Debug)]
 enum NixpkgsCommit {
     /// Package with this name was added
-    Add(String),
+    Add(PkgAttr),
     /// Package with this name was removed
-    Remove(String),
-    /// Package with this name was updated
-    Update(String, String),
+    Remove(PkgAttr),
+    /// Package with this name was updated (name, old version, new version)
+    Update(PkgAttr, String, String),
     /// Could not parse commit message
     Unparsable(String),
+    /// Could not parse commit message, but it starts with a skip prefix so it was never about a package (ex. a merge)
+    NonPackage(String),
 }
 
 impl NixpkgsCommit {
-    fn new(commit_message: &String) -> NixpkgsCommit {
-        let regex_str = Regex::new(
-            r"^(?:\[.+\] )?(?<name>\S+): (?<action>drop|init|(?:[A-Za-z0-9-.]+ -> [A-Za-z0-9-.]+))",
-        )
-        .unwrap();
-
-        let captures = regex_str.captures(commit_message.as_str());
+    /// Parses a commit message into a change for each package it names, along with the branch tag it starts with (ex. 24.05 for `[24.05] foo: 1.0 -> 2.0`). Several names with one version change all get that change, several names with as many version changes are paired in order, and one name with several version changes gets each of them in order.
+    fn parse(commit_message: &str) -> (Vec<NixpkgsCommit>, Option<String>) {
+        let captures = COMMIT_REGEX.captures(commit_message);
+        let tag = captures
+            .as_ref()
+            .and_then(|caps| caps.name("tag"))
+            .map(|m| m.as_str().into());
 
         if let Some(caps) = captures {
-            let name: String = caps.name("name").map(|m| m.as_str().into()).unwrap();
-            let action: String = caps.name("action").map(|m| m.as_str().into()).unwrap();
+            let names: Vec<PkgAttr> = caps["names"]
+                .split(',')
+                .map(|val| PkgAttr::from(val.trim().to_string()))
+                .collect();
+            let action = &caps["action"];
 
-            match action.as_str() {
-                "init" => {
-                    return NixpkgsCommit::Add(name);
+            if names.iter().all(|val| !val.name.is_empty()) {
+                match action {
+                    "init" => {
+                        trace!("commit `{}`: init of {:?}", commit_message, names);
+                        return (names.into_iter().map(NixpkgsCommit::Add).collect(), tag);
+                    }
+                    "drop" => {
+                        trace!("commit `{}`: drop of {:?}", commit_message, names);
+                        return (names.into_iter().map(NixpkgsCommit::Remove).collect(), tag);
+                    }
+                    _ => {
+                        // The regex makes sure every part holds an arrow
+                        let changes: Vec<(&str, &str)> = action
+                            .split(", ")
+                            .map(|val| val.split_once(" -> ").unwrap())
+                            .collect();
+                        trace!(
+                            "commit `{}`: update of {:?} ({:?})",
+                            commit_message, names, changes
+                        );
+
+                        let update = |name: &PkgAttr, (old, new): &(&str, &str)| {
+                            NixpkgsCommit::Update(name.clone(), (*old).into(), (*new).into())
+                        };
+                        match (names.len(), changes.len()) {
+                            (_, 1) => {
+                                let updates = names.iter().map(|val| update(val, &changes[0]));
+                                return (updates.collect(), tag);
+                            }
+                            (1, _) => {
+                                let updates = changes.iter().map(|val| update(&names[0], val));
+                                return (updates.collect(), tag);
+                            }
+                            (names_len, changes_len) if names_len == changes_len => {
+                                let updates = names
+                                    .iter()
+                                    .zip(changes.iter())
+                                    .map(|(name, change)| update(name, change));
+                                return (updates.collect(), tag);
+                            }
+                            _ => (),
+                        }
+                    }
                 }
-                "drop" => return NixpkgsCommit::Remove(name),
-                _ => return NixpkgsCommit::Update(name, action),
             }
         }
 
-        NixpkgsCommit::Unparsable(commit_message.clone())
+        trace!("commit `{}`: no match, unparsable", commit_message);
+        (vec![NixpkgsCommit::Unparsable(commit_message.into())], tag)
     }
 }
 
-/// A struct used to generate a report about a nixpkgs diff
+/// The net change to a package over all the commits in a range
+enum NetChange {
+    /// The package was added
+    Added,
+    /// The package was removed
+    Removed,
+    /// The package was updated from the oldest to the newest version seen, in this many version bumps
+    Updated {
+        old: String,
+        new: String,
+        steps: usize,
+    },
+}
+
+/// The details Github gives about a commit
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+pub struct CommitDetails {
+    /// The full commit message
+    pub message: String,
+    /// When the commit was authored as an RFC 3339 string (as Github gives it)
+    pub date: Option<String>,
+    /// The commit hash
+    pub sha: Option<String>,
+}
+
+impl CommitDetails {
+    /// Pulls the commit messages, author dates, and hashes out of Github compare json (one page of it)
+    pub fn from_compare(compare_json: &Value) -> Result<Vec<CommitDetails>, CompareParseError> {
+        compare_json["commits"]
+            .as_array()
+            .ok_or(CompareParseError::MissingCommits)?
+            .iter()
+            .enumerate()
+            .map(|(i, val)| {
+                let commit = &val["commit"];
+                Ok(CommitDetails {
+                    message: commit["message"]
+                        .as_str()
+                        .ok_or(CompareParseError::MalformedCommit(i))?
+                        .into(),
+                    date: commit["author"]["date"].as_str().map(|date| date.into()),
+                    sha: val["sha"].as_str().map(|sha| sha.into()),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Errors produced when Github compare json can't be read
 #[derive(PartialEq, Eq, Clone, Debug)]
-pub struct Nixpkgs(Vec<NixpkgsCommit>);
+pub enum CompareParseError {
+    /// The text is not valid json (holds the serde error)
+    InvalidJson(String),
+    /// The json has no commits list
+    MissingCommits,
+    /// The commit at this index has no message
+    MalformedCommit(usize),
+}
+
+impl fmt::Display for CompareParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompareParseError::InvalidJson(err) => write!(f, "compare json is invalid: {}", err),
+            CompareParseError::MissingCommits => write!(f, "compare json has no commits"),
+            CompareParseError::MalformedCommit(i) => {
+                write!(f, "commit {} in the compare json has no message", i)
+            }
+        }
+    }
+}
+
+impl error::Error for CompareParseError {}
+
+/// A parsed commit along with the details kept for the report
+#[derive(PartialEq, Eq, Clone, Debug)]
+struct NixpkgsCommitData {
+    /// The change to each package the commit names
+    commits: Vec<NixpkgsCommit>,
+    /// The branch tag the subject started with (ex. staging-next or 24.05)
+    tag: Option<String>,
+    /// The full commit message, kept so the body can be searched
+    message: String,
+    date: Option<OffsetDateTime>,
+    sha: Option<String>,
+}
+
+/// A struct used to generate a report about a nixpkgs diff. Each commit can carry the date it was authored and its hash.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Nixpkgs(Vec<NixpkgsCommitData>);
 
 impl Nixpkgs {
-    pub fn new(commits: &Vec<String>) -> Nixpkgs {
-        Nixpkgs(commits.iter().map(|val| NixpkgsCommit::new(val)).collect())
+    pub fn new(commits: &[String]) -> Nixpkgs {
+        let npkgs = Nixpkgs(
+            commits
+                .iter()
+                .map(|val| {
+                    let (commits, tag) = NixpkgsCommit::parse(val);
+                    NixpkgsCommitData {
+                        commits,
+                        tag,
+                        message: val.clone(),
+                        date: None,
+                        sha: None,
+                    }
+                })
+                .collect(),
+        );
+        npkgs.classified()
+    }
+
+    /// Builds from commits with their details. Dates that can't be parsed are dropped.
+    pub fn new_with_details(commits: &[CommitDetails]) -> Nixpkgs {
+        let npkgs = Nixpkgs(
+            commits
+                .iter()
+                .map(|val| {
+                    let (commits, tag) = NixpkgsCommit::parse(&val.message);
+                    NixpkgsCommitData {
+                        commits,
+                        tag,
+                        message: val.message.clone(),
+                        date: val
+                            .date
+                            .as_ref()
+                            .and_then(|date| OffsetDateTime::parse(date, &Rfc3339).ok()),
+                        sha: val.sha.clone(),
+                    }
+                })
+                .collect(),
+        );
+        npkgs.classified()
     }
 
-    pub fn generate_report(&self, base_hash: &String, head_hash: &String) -> String {
-        // Turn commits into hash sets TODO: find out why multiple appear
-        let added: HashSet<String> = self
-            .0
+    /// Builds from the raw json Github's compare api returns (ex. `gh api repos/NixOS/nixpkgs/compare/a...b`)
+    pub fn from_compare_json(json: &str) -> Result<Nixpkgs, CompareParseError> {
+        let compare_json: Value = serde_json::from_str(json)
+            .map_err(|err| CompareParseError::InvalidJson(err.to_string()))?;
+        Ok(Nixpkgs::new_with_details(&CommitDetails::from_compare(
+            &compare_json,
+        )?))
+    }
+
+    /// Sorts out the non-package commits with the default skip prefixes and logs how many of the commits could be parsed
+    fn classified(mut self) -> Nixpkgs {
+        self.set_skip_prefixes(&DEFAULT_SKIP_PREFIXES);
+        self.log_parsed();
+        self
+    }
+
+    /// Logs how many of the commits could be parsed
+    fn log_parsed(&self) {
+        let count = |pick: fn(&NixpkgsCommit) -> bool| {
+            self.0
+                .iter()
+                .filter(|val| matches!(&val.commits[..], [commit] if pick(commit)))
+                .count()
+        };
+        debug!(
+            "parsed {} commits, {} unparsable, {} non-package",
+            self.0.len(),
+            count(|val| matches!(val, NixpkgsCommit::Unparsable(_))),
+            count(|val| matches!(val, NixpkgsCommit::NonPackage(_)))
+        );
+    }
+
+    /// Counts commits that could not be parsed and start with one of the prefixes (ex. Merge or treewide) as non-package commits instead of unparsable ones. Replaces the prefixes used before, the defaults are DEFAULT_SKIP_PREFIXES, and an empty list counts every one of them as unparsable.
+    pub fn set_skip_prefixes(&mut self, prefixes: &[impl AsRef<str>]) {
+        let skipped = |message: &str| {
+            prefixes
+                .iter()
+                .map(|val| val.as_ref())
+                .any(|prefix| !prefix.is_empty() && message.starts_with(prefix))
+        };
+        for val in self.0.iter_mut() {
+            for commit in val.commits.iter_mut() {
+                if let NixpkgsCommit::Unparsable(message) | NixpkgsCommit::NonPackage(message) =
+                    commit
+                {
+                    *commit = match skipped(message) {
+                        true => NixpkgsCommit::NonPackage(std::mem::take(message)),
+                        false => NixpkgsCommit::Unparsable(std::mem::take(message)),
+                    };
+                }
+            }
+        }
+    }
+
+    /// Drops commits authored outside of the date range (both ends inclusive). Commits without a date are kept since there is nothing to check.
+    pub fn retain_dates(&mut self, since: Option<Date>, until: Option<Date>) {
+        self.0.retain(|val| match val.date {
+            Some(date) => {
+                since.is_none_or(|val| date.date() >= val)
+                    && until.is_none_or(|val| date.date() <= val)
+            }
+            None => true,
+        });
+    }
+
+    /// Only keeps commits whose subject started with the branch tag (ex. 24.05 keeps `[24.05] foo: 1.0 -> 2.0`)
+    pub fn retain_tag(&mut self, tag: &str) {
+        self.0.retain(|val| val.tag.as_deref() == Some(tag));
+    }
+
+    /// Drops changes to packages whose name the filter doesn't keep, along with commits left with no changes (unparsable commits have no name so they are dropped too)
+    pub fn retain_names(&mut self, filter: &NameFilter) {
+        for val in self.0.iter_mut() {
+            val.commits.retain(|commit| match commit {
+                NixpkgsCommit::Add(name)
+                | NixpkgsCommit::Remove(name)
+                | NixpkgsCommit::Update(name, _, _) => filter.matches(&name.name),
+                NixpkgsCommit::Unparsable(_) | NixpkgsCommit::NonPackage(_) => false,
+            });
+        }
+        self.0.retain(|val| !val.commits.is_empty());
+    }
+
+    /// Finds the commits whose full message mentions any of the keywords, ignoring case (ex. CVE matches `fixes cve-2024-1234`), in commit order
+    pub fn find_keywords(&self, keywords: &[String]) -> Vec<KeywordCommit> {
+        let keywords: Vec<(&String, String)> = keywords
             .iter()
-            .filter_map(|val| match val {
-                NixpkgsCommit::Add(name) => Some(format!(" - {}\n", name)),
-                _ => None,
-            })
+            .filter(|val| !val.is_empty())
+            .map(|val| (val, val.to_lowercase()))
             .collect();
 
-        let mut updated: Vec<String> = self
-            .0
+        self.0
             .iter()
-            .filter_map(|val| match val {
-                NixpkgsCommit::Update(name, version) => Some(format!(" - {}: {}\n", name, version)),
-                _ => None,
+            .filter_map(|val| {
+                let message = val.message.to_lowercase();
+                let matched: Vec<String> = keywords
+                    .iter()
+                    .filter(|(_, keyword)| message.contains(keyword.as_str()))
+                    .map(|(keyword, _)| (*keyword).clone())
+                    .collect();
+                (!matched.is_empty()).then(|| KeywordCommit {
+                    subject: val.message.lines().next().unwrap_or("").into(),
+                    keywords: matched,
+                    sha: val.sha.clone(),
+                })
             })
-            .collect::<HashSet<String>>()
-            .iter()
-            .map(|val| val.clone())
-            .collect();
-        updated.sort();
+            .collect()
+    }
+
+    /// Collect the report data without rendering it
+    pub fn collect_report(&self, repo: &str, base_hash: &str, head_hash: &str) -> NixpkgsReport {
+        // Walk the commits in order to find the net change of each package
+        let mut changes: BTreeMap<&PkgAttr, NetChange> = BTreeMap::new();
+        let mut unparsable: Vec<String> = vec![];
+        let mut non_package: usize = 0;
+        let mut shas: BTreeMap<String, String> = BTreeMap::new();
+        let mut tags: BTreeMap<String, String> = BTreeMap::new();
+        let commits = self.0.iter().flat_map(|val| {
+            val.commits
+                .iter()
+                .map(move |commit| (commit, &val.sha, &val.tag))
+        });
+        for (commit, sha, tag) in commits {
+            // The newest tagged commit decides a package's badge
+            if let (
+                Some(tag),
+                NixpkgsCommit::Add(name)
+                | NixpkgsCommit::Remove(name)
+                | NixpkgsCommit::Update(name, _, _),
+            ) = (tag, commit)
+            {
+                tags.insert(name.name.clone(), tag.clone());
+            }
+
+            // Later commits are newer, so they replace the commit a package links to
+            if let (
+                Some(sha),
+                NixpkgsCommit::Add(name)
+                | NixpkgsCommit::Remove(name)
+                | NixpkgsCommit::Update(name, _, _),
+            ) = (sha, commit)
+            {
+                shas.insert(name.name.clone(), sha.clone());
+            }
+
+            match commit {
+                NixpkgsCommit::Add(name) => match changes.get(name) {
+                    // Dropped earlier and brought back, so it exists on both ends
+                    Some(NetChange::Removed) => {
+                        changes.remove(name);
+                    }
+                    _ => {
+                        changes.insert(name, NetChange::Added);
+                    }
+                },
+                NixpkgsCommit::Remove(name) => match changes.get(name) {
+                    // Added and dropped within the range, so it exists on neither end
+                    Some(NetChange::Added) => {
+                        changes.remove(name);
+                    }
+                    _ => {
+                        changes.insert(name, NetChange::Removed);
+                    }
+                },
+                NixpkgsCommit::Update(name, old, new) => match changes.get_mut(name) {
+                    None => {
+                        changes.insert(
+                            name,
+                            NetChange::Updated {
+                                old: old.clone(),
+                                new: new.clone(),
+                                steps: 1,
+                            },
+                        );
+                    }
+                    // Keep the oldest version and move to the newest, only counting commits that moved the version (not repeats like backports)
+                    Some(NetChange::Updated {
+                        new: latest, steps, ..
+                    }) => {
+                        if latest != new {
+                            *latest = new.clone();
+                            *steps += 1;
+                        }
+                    }
+                    // Added and removed packages stay that way
+                    Some(_) => (),
+                },
+                NixpkgsCommit::Unparsable(message) => {
+                    unparsable.push(message.lines().next().unwrap_or("").into())
+                }
+                NixpkgsCommit::NonPackage(_) => non_package += 1,
+            }
+        }
+
+        // Split into lists (already sorted by name thanks to the BTreeMap)
+        let mut added: Vec<PkgAttr> = vec![];
+        let mut updated: Vec<NixpkgsUpdate> = vec![];
+        let mut removed: Vec<PkgAttr> = vec![];
+        let listed: BTreeSet<&str> = changes.keys().map(|attr| attr.name.as_str()).collect();
+        shas.retain(|name, _| listed.contains(name.as_str()));
+        tags.retain(|name, _| listed.contains(name.as_str()));
+        for (name, change) in changes {
+            match change {
+                NetChange::Added => added.push(name.clone()),
+                NetChange::Updated { old, new, steps } => updated.push(NixpkgsUpdate {
+                    name: name.clone(),
+                    change: format!("{} -> {}", old, new),
+                    steps,
+                }),
+                NetChange::Removed => removed.push(name.clone()),
+            }
+        }
+
+        // Commits aren't always in date order so use the earliest and latest seen
+        let dates = self.0.iter().filter_map(|val| val.date);
+        let date_string = |date: Option<OffsetDateTime>| date.map(|val| val.date().to_string());
+
+        NixpkgsReport {
+            schema_version: REPORT_SCHEMA_VERSION,
+            repo: repo.into(),
+            base: base_hash.into(),
+            head: head_hash.into(),
+            base_ref: None,
+            head_ref: None,
+            commits: self.0.len(),
+            first_date: date_string(dates.clone().min()),
+            last_date: date_string(dates.max()),
+            added,
+            updated,
+            removed,
+            unparsable,
+            non_package,
+            renamed: None,
+            security: None,
+            shas,
+            tags,
+            filter: None,
+        }
+    }
+}
 
-        let removed: HashSet<String> = self
-            .0
+// --- REPORT
+/// The version of the json report layout. Bump this whenever a field is renamed, removed, or changes meaning.
+pub const REPORT_SCHEMA_VERSION: u32 = 1;
+
+/// The template to_markdown uses, see NixpkgsReport::template_context for the placeholders
+pub const NIXPKGS_TEMPLATE: &str = "## {{heading}}\n\
+    Hash: `{{base_label}} -> {{head_label}}`\n\
+    {{commits}} commits{{date_range}}\n\
+    Report generated using [`nix-update-report`](https://github.com/aldenparker/nix-update-report.git).\n\
+    \n\
+    ### Stats\n\
+    {{stats}}\n\
+    {{security_section}}\
+    ### Added\n\
+    {{added}}\n\
+    ### Updated\n\
+    {{updated}}\n\
+    ### Removed\n\
+    {{removed}}\n\
+    {{renamed_section}}\
+    {{unparsable_section}}\
+    {{footer}}";
+
+/// A commit whose message mentions one of the keywords it was searched for
+#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+pub struct KeywordCommit {
+    /// The first line of the commit message
+    pub subject: String,
+    /// The keywords the message mentions, as they were given
+    pub keywords: Vec<String>,
+    /// The commit hash, if it is known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha: Option<String>,
+}
+
+/// A single package update in a nixpkgs report
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
+pub struct NixpkgsUpdate {
+    /// The name of the updated package
+    pub name: PkgAttr,
+    /// The version change (ex. 1.0.0 -> 2.0.0)
+    pub change: String,
+    /// How many version bumps the change was made in, more than one for packages updated several times in the range
+    #[serde(default = "NixpkgsUpdate::one_step")]
+    pub steps: usize,
+}
+
+impl NixpkgsUpdate {
+    /// The steps of updates read from reports made before steps were counted
+    fn one_step() -> usize {
+        1
+    }
+
+    /// Notes how many bumps the change took (ex. ` (3 bumps)`), empty if it only took one
+    pub fn steps_note(&self) -> String {
+        match self.steps {
+            0 | 1 => "".into(),
+            steps => format!(" ({} bumps)", steps),
+        }
+    }
+
+    /// Finds which way the version moved. Returns none if either version is unparsable.
+    pub fn direction(&self) -> Option<VersionDirection> {
+        let (old, new) = self.change.split_once(" -> ")?;
+        PkgVersion::new(old).direction(&PkgVersion::new(new))
+    }
+
+    /// Finds how big the version bump was. Returns unknown if the change can't be split into versions.
+    pub fn bump_level(&self) -> BumpLevel {
+        self.change
+            .split_once(" -> ")
+            .map_or(BumpLevel::Unknown, |(old, new)| {
+                PkgVersion::new(old).bump_level(&PkgVersion::new(new))
+            })
+    }
+}
+
+/// Options that change how a nixpkgs report is rendered in markdown
+#[derive(Default, Clone, Debug)]
+pub struct NixpkgsReportOptions {
+    /// Group each package list by its top-level package set
+    pub group_by_set: bool,
+    /// Link package names to search.nixos.org
+    pub link_packages: bool,
+    /// Link each package to the most recent commit that touched it
+    pub link_commits: bool,
+    /// Only list this many entries of each package list (the stats still count all of them)
+    pub limit: Option<usize>,
+    /// A title added to the end of the heading
+    pub title: Option<String>,
+    /// The order the updated packages are listed in
+    pub update_order: UpdateOrder,
+}
+
+/// All the data collected from a nixpkgs diff, ready to be rendered
+#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+pub struct NixpkgsReport {
+    /// The json layout version, see REPORT_SCHEMA_VERSION
+    pub schema_version: u32,
+    /// The repository the commits were compared in (owner/name)
+    pub repo: String,
+    /// The base commit hash
+    pub base: String,
+    /// The head commit hash
+    pub head: String,
+    /// The branch or tag the base hash was resolved from, none if a hash was given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_ref: Option<String>,
+    /// The branch or tag the head hash was resolved from, none if a hash was given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub head_ref: Option<String>,
+    /// The number of commits in the range
+    pub commits: usize,
+    /// The date of the earliest commit (ex. 2024-01-31), none if no commits had dates
+    pub first_date: Option<String>,
+    /// The date of the latest commit, none if no commits had dates
+    pub last_date: Option<String>,
+    /// All packages that were added, sorted by name
+    pub added: Vec<PkgAttr>,
+    /// All packages that were updated, sorted by name
+    pub updated: Vec<NixpkgsUpdate>,
+    /// All packages that were removed, sorted by name
+    pub removed: Vec<PkgAttr>,
+    /// The subject lines of commits that could not be parsed, in commit order
+    pub unparsable: Vec<String>,
+    /// The number of commits that don't change a package (ex. merges), left out of the unparsable commits
+    #[serde(default)]
+    pub non_package: usize,
+    /// Removed and added packages that look like renames as (old, new), none if renames were not looked for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub renamed: Option<Vec<(PkgAttr, PkgAttr)>>,
+    /// The commits whose message mentions a security keyword (ex. CVE), in commit order, none if they were not looked for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub security: Option<Vec<KeywordCommit>>,
+    /// The most recent commit that touched each listed package, by package name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub shas: BTreeMap<String, String>,
+    /// The branch tag of the most recent tagged commit that touched each listed package, by package name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+    /// A description of the filter package names went through, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter: Option<String>,
+}
+
+impl NixpkgsReport {
+    /// Grab the name part of the repository (ex. nixpkgs for NixOS/nixpkgs)
+    fn repo_name(&self) -> &str {
+        self.repo
+            .split_once('/')
+            .map_or(self.repo.as_str(), |(_, name)| name)
+    }
+
+    /// The report heading (ex. nix-update-report - nixpkgs - staging week 12)
+    fn heading(&self, options: &NixpkgsReportOptions) -> String {
+        format!(
+            "nix-update-report - {}{}",
+            self.repo_name(),
+            options
+                .title
+                .as_ref()
+                .map_or("".into(), |val| format!(" - {}", val))
+        )
+    }
+
+    /// Grab the short hash and Github url of the commit a package links to, if commit links were asked for
+    fn commit_link(
+        &self,
+        attr: &PkgAttr,
+        options: &NixpkgsReportOptions,
+    ) -> Option<(String, String)> {
+        self.sha_link(&self.shas.get(&attr.name).cloned(), options)
+    }
+
+    /// Grab the short hash and Github url of a commit, if commit links were asked for
+    fn sha_link(
+        &self,
+        sha: &Option<String>,
+        options: &NixpkgsReportOptions,
+    ) -> Option<(String, String)> {
+        let sha = sha.as_ref().filter(|_| options.link_commits)?;
+        Some((
+            sha.get(..7).unwrap_or(sha).to_string(),
+            format!("https://github.com/{}/commit/{}", self.repo, sha),
+        ))
+    }
+
+    /// Borrows the updated packages in the order they should be listed
+    fn ordered_updates(&self, order: UpdateOrder) -> Vec<&NixpkgsUpdate> {
+        let mut updates: Vec<&NixpkgsUpdate> = self.updated.iter().collect();
+        // The list is already sorted by name and the sort is stable, so names stay sorted within each bump
+        if order == UpdateOrder::Bump {
+            updates.sort_by_key(|val| val.bump_level());
+        }
+        updates
+    }
+
+    /// Join the lines of a package list, grouping them by package set if asked to
+    fn render_list(lines: &[(&PkgAttr, String)], options: &NixpkgsReportOptions) -> String {
+        // Only the first entries are listed if there is a limit
+        let (lines, hidden) = limit_list(lines, options.limit);
+        let more = match hidden {
+            0 => "".into(),
+            _ => format!("...and {} more\n", hidden),
+        };
+
+        if !options.group_by_set {
+            let mut list: String = lines.iter().map(|(_, line)| line.as_str()).collect();
+            list.push_str(&more);
+            return list;
+        }
+
+        // Top-level packages (no set) sort first
+        let mut groups: BTreeMap<Option<&str>, Vec<&str>> = BTreeMap::new();
+        for (attr, line) in lines {
+            groups
+                .entry(attr.set.as_deref())
+                .or_default()
+                .push(line.as_str());
+        }
+
+        groups
             .iter()
-            .filter_map(|val| match val {
-                NixpkgsCommit::Remove(name) => Some(format!(" - {}\n", name)),
-                _ => None,
+            .map(|(set, group_lines)| {
+                format!(
+                    "<details>\n\
+                    <summary>{} ({})</summary>\n\
+                    \n\
+                    {}\n\
+                    </details>\n\
+                    ",
+                    set.unwrap_or("top-level"),
+                    group_lines.len(),
+                    group_lines.concat()
+                )
             })
-            .collect();
+            .chain((hidden > 0).then_some(more))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    /// Pair removed and added packages with similar names in the same package set as renames
+    pub fn detect_renames(&mut self) {
+        self.renamed = Some(pair_renames(
+            &mut self.removed,
+            &mut self.added,
+            |attr| attr.name.as_str(),
+            |old_attr, new_attr| old_attr.set == new_attr.set,
+        ));
+    }
+
+    /// Checks if any packages were removed
+    pub fn has_removals(&self) -> bool {
+        !self.removed.is_empty()
+    }
+
+    /// Describes every removed package, one line each (ex. `python3Packages.foo removed`)
+    pub fn removal_lines(&self) -> Vec<String> {
+        self.removed
+            .iter()
+            .map(|val| format!("{} removed", val))
+            .collect()
+    }
 
-        let mut report = format!(
-            "## nix-update-report - nixpkgs\n\
-            Hash: `{} -> {}`\n\
-            Report generated using [`nix-update-report`](https://github.com/aldenparker/nix-update-report.git).\n\
-            \n\
-            ### Stats\n\
+    /// Describes every downgraded package, one line each (ex. `foo: 1.2 -> 1.1`)
+    pub fn downgrade_lines(&self) -> Vec<String> {
+        self.updated
+            .iter()
+            .filter(|val| val.direction() == Some(VersionDirection::Downgrade))
+            .map(|val| format!("{}: {}", val.name, val.change))
+            .collect()
+    }
+
+    /// Checks if any package versions went backwards
+    pub fn has_downgrades(&self) -> bool {
+        self.updated
+            .iter()
+            .any(|val| val.direction() == Some(VersionDirection::Downgrade))
+    }
+
+    /// Shortens a hash like git does, refs are left alone
+    fn short_hash(hash: &str) -> &str {
+        match hash.chars().all(|val| val.is_ascii_hexdigit()) {
+            true => hash.get(..7).unwrap_or(hash),
+            false => hash,
+        }
+    }
+
+    /// Names an end of the range, with the branch or tag it was resolved from if there was one (ex. `nixos-unstable (abcdef1)`)
+    fn ref_label(hash: &str, git_ref: &Option<String>) -> String {
+        match git_ref {
+            Some(git_ref) => format!("{} ({})", git_ref, Self::short_hash(hash)),
+            None => hash.into(),
+        }
+    }
+
+    /// Render a one line summary of the report (ex. `nixpkgs abcdef1..1234567: +42 ~108 -7`)
+    pub fn stat_line(&self) -> String {
+        format!(
+            "{} {}..{}: +{} ~{} -{}",
+            self.repo_name(),
+            Self::short_hash(&self.base),
+            Self::short_hash(&self.head),
+            self.added.len(),
+            self.updated.len(),
+            self.removed.len()
+        )
+    }
+
+    /// Render the report in markdown
+    pub fn to_markdown(&self, options: &NixpkgsReportOptions) -> String {
+        template::render(NIXPKGS_TEMPLATE, &self.template_context(options))
+    }
+
+    /// Collect the rendered pieces of the report a template can use. Holds `heading`, `title`, `repo`, `base`, `head` (the hashes), `base_label`, `head_label` (the hashes with the branch or tag they were resolved from), `commits`, `date_range`, `stats`, the `added`, `updated`, and `removed` lists, the optional `renamed_section`, `security_section`, and `unparsable_section`, the `added_count`, `updated_count`, and `removed_count` numbers, and `version`, `generated_at`, and `footer` for when and with what the report was made.
+    pub fn template_context(&self, options: &NixpkgsReportOptions) -> TemplateContext {
+        let generated_at = template::generated_at();
+        let stats = format!(
+            "{}\
             Pkgs Added: {}\n\
             Pkg Updates: {}\n\
             Pkgs Removed: {}\n\
-            \n\
+            Net Pkgs: {}\n\
+            {}\
+            {}\
+            Non-Package Commits: {}\n\
+            Unparsable Commits: {}\n\
             ",
-            base_hash,
-            head_hash,
-            added.len(),
-            updated.len(),
-            removed.len()
+            self.filter.as_ref().map_or("".into(), |val| format!(
+                "Only counting packages {}\n\n",
+                val
+            )),
+            self.added.len(),
+            self.updated.len(),
+            self.removed.len(),
+            net_change(self.added.len(), self.removed.len()),
+            self.renamed
+                .as_ref()
+                .map_or("".into(), |val| format!("Pkgs Renamed: {}\n", val.len())),
+            self.security.as_ref().map_or("".into(), |val| format!(
+                "Security-Relevant Commits: {}\n",
+                val.len()
+            )),
+            self.non_package,
+            self.unparsable.len()
         );
 
-        let pkg_changes: String = format!(
-            "### Added\n\
-            {}\n\
-            ### Updated\n\
-            {}\n\
-            ### Removed\n\
-            {}\n\
-            ",
-            added.iter().fold("".into(), |mut acc: String, val| {
-                acc.push_str(&val);
-                acc
-            }),
-            updated.iter().fold("".into(), |mut acc: String, val| {
-                acc.push_str(&val);
-                acc
-            }),
-            removed.iter().fold("".into(), |mut acc: String, val| {
-                acc.push_str(&val);
-                acc
+        // Package names and commits become links if asked for, and names get a badge if their commit was tagged
+        let name = |attr: &PkgAttr| {
+            let badge = self
+                .tags
+                .get(&attr.name)
+                .map_or("".into(), |tag| format!(" `[{}]`", tag));
+            match options.link_packages {
+                true => format!("[{}]({}){}", attr, attr.search_url(), badge),
+                false => format!("{}{}", attr, badge),
+            }
+        };
+        let commit = |attr: &PkgAttr| {
+            self.commit_link(attr, options)
+                .map_or("".into(), |(short, url)| format!(" ([{}]({}))", short, url))
+        };
+
+        let added: Vec<(&PkgAttr, String)> = self
+            .added
+            .iter()
+            .map(|val| (val, format!(" - {}{}\n", name(val), commit(val))))
+            .collect();
+        let updated: Vec<(&PkgAttr, String)> = self
+            .ordered_updates(options.update_order)
+            .into_iter()
+            .map(|val| {
+                (
+                    &val.name,
+                    format!(
+                        " - {}: {}{}{}\n",
+                        name(&val.name),
+                        val.change,
+                        val.steps_note(),
+                        commit(&val.name)
+                    ),
+                )
             })
+            .collect();
+        let removed: Vec<(&PkgAttr, String)> = self
+            .removed
+            .iter()
+            .map(|val| (val, format!(" - {}{}\n", name(val), commit(val))))
+            .collect();
+
+        // Only list renames if they were looked for
+        let renamed_section = self.renamed.as_ref().map_or("".into(), |renamed| {
+            let renamed: Vec<(&PkgAttr, String)> = renamed
+                .iter()
+                .map(|(old_attr, new_attr)| {
+                    (
+                        new_attr,
+                        format!(
+                            " - {} -> {}{}\n",
+                            old_attr,
+                            name(new_attr),
+                            commit(new_attr)
+                        ),
+                    )
+                })
+                .collect();
+            format!(
+                "### Renamed\n\
+                {}\n\
+                ",
+                Self::render_list(&renamed, options)
+            )
+        });
+
+        // Only list security-relevant commits if they were looked for
+        let security_section = self.security.as_ref().map_or("".into(), |security| {
+            format!(
+                "### Security-Relevant\n\
+                {}\n\
+                ",
+                security
+                    .iter()
+                    .map(|val| format!(
+                        " - {} ({}){}\n",
+                        val.subject,
+                        val.keywords.join(", "),
+                        self.sha_link(&val.sha, options)
+                            .map_or("".into(), |(short, url)| format!(" ([{}]({}))", short, url))
+                    ))
+                    .reduce(|mut acc, e| {
+                        acc.push_str(e.as_str());
+                        acc
+                    })
+                    .unwrap_or("None\n".into())
+            )
+        });
+
+        // Only list unparsable commits when there are some
+        let unparsable_section = match self.unparsable.is_empty() {
+            true => "".into(),
+            false => format!(
+                "### Unparsable\n\
+                <details>\n\
+                <summary>{} commits</summary>\n\
+                \n\
+                {}\n\
+                </details>\n\
+                ",
+                self.unparsable.len(),
+                self.unparsable
+                    .iter()
+                    .map(|val| format!(" - {}\n", val))
+                    .collect::<String>()
+            ),
+        };
+
+        TemplateContext::from([
+            ("heading", self.heading(options)),
+            ("title", options.title.clone().unwrap_or("".into())),
+            ("version", template::VERSION.into()),
+            ("generated_at", generated_at.clone()),
+            ("footer", template::footer(&generated_at)),
+            ("repo", self.repo.clone()),
+            ("base", self.base.clone()),
+            ("head", self.head.clone()),
+            ("base_label", Self::ref_label(&self.base, &self.base_ref)),
+            ("head_label", Self::ref_label(&self.head, &self.head_ref)),
+            ("commits", self.commits.to_string()),
+            (
+                "date_range",
+                match (&self.first_date, &self.last_date) {
+                    (Some(first), Some(last)) => format!(" from {} to {}", first, last),
+                    _ => "".into(),
+                },
+            ),
+            ("stats", stats),
+            ("added", Self::render_list(&added, options)),
+            ("updated", Self::render_list(&updated, options)),
+            ("removed", Self::render_list(&removed, options)),
+            ("renamed_section", renamed_section),
+            ("security_section", security_section),
+            ("unparsable_section", unparsable_section),
+            ("added_count", self.added.len().to_string()),
+            ("updated_count", self.updated.len().to_string()),
+            ("removed_count", self.removed.len().to_string()),
+        ])
+    }
+
+    /// Render the report as a self-contained html document with every list collapsed
+    pub fn to_html(&self, options: &NixpkgsReportOptions) -> String {
+        let mut body = format!(
+            "<p>Hash: <code>{} -&gt; {}</code><br>\n\
+            {} commits{}</p>\n\
+            <h3>Stats</h3>\n\
+            {}\
+            {}",
+            html::escape(&Self::ref_label(&self.base, &self.base_ref)),
+            html::escape(&Self::ref_label(&self.head, &self.head_ref)),
+            self.commits,
+            match (&self.first_date, &self.last_date) {
+                (Some(first), Some(last)) => format!(" from {} to {}", first, last),
+                _ => "".into(),
+            },
+            self.filter.as_ref().map_or("".into(), |val| format!(
+                "<p>Only counting packages {}</p>\n",
+                html::escape_code(val)
+            )),
+            html::table(
+                &["Stat".into(), "Count".into()],
+                &[
+                    Some(("Pkgs Added", self.added.len().to_string())),
+                    Some(("Pkg Updates", self.updated.len().to_string())),
+                    Some(("Pkgs Removed", self.removed.len().to_string())),
+                    Some(("Net Pkgs", net_change(self.added.len(), self.removed.len()),)),
+                    self.renamed
+                        .as_ref()
+                        .map(|val| ("Pkgs Renamed", val.len().to_string())),
+                    self.security
+                        .as_ref()
+                        .map(|val| ("Security-Relevant Commits", val.len().to_string())),
+                    Some(("Non-Package Commits", self.non_package.to_string())),
+                    Some(("Unparsable Commits", self.unparsable.len().to_string())),
+                ]
+                .into_iter()
+                .flatten()
+                .map(|(name, count)| vec![name.into(), count])
+                .collect::<Vec<Vec<String>>>()
+            )
         );
 
-        report.push_str(&pkg_changes);
-        report
+        // Package names become links if asked for, and get a badge if their commit was tagged
+        let name = |attr: &PkgAttr| {
+            let badge = self.tags.get(&attr.name).map_or("".into(), |tag| {
+                format!(" <code>[{}]</code>", html::escape(tag))
+            });
+            match options.link_packages {
+                true => format!(
+                    "<a href=\"{}\">{}</a>{}",
+                    attr.search_url(),
+                    html::escape(&attr.name),
+                    badge
+                ),
+                false => format!("{}{}", html::escape(&attr.name), badge),
+            }
+        };
+        let commit = |attr: &PkgAttr| {
+            self.commit_link(attr, options)
+                .map_or("".into(), |(short, url)| {
+                    format!(" (<a href=\"{}\"><code>{}</code></a>)", url, short)
+                })
+        };
+
+        let added: Vec<(&PkgAttr, String)> = self
+            .added
+            .iter()
+            .map(|val| (val, format!("{}{}", name(val), commit(val))))
+            .collect();
+        let updated: Vec<(&PkgAttr, String)> = self
+            .ordered_updates(options.update_order)
+            .into_iter()
+            .map(|val| {
+                (
+                    &val.name,
+                    format!(
+                        "{}: {}{}{}",
+                        name(&val.name),
+                        html::escape(&val.change),
+                        val.steps_note(),
+                        commit(&val.name)
+                    ),
+                )
+            })
+            .collect();
+        let removed: Vec<(&PkgAttr, String)> = self
+            .removed
+            .iter()
+            .map(|val| (val, format!("{}{}", name(val), commit(val))))
+            .collect();
+        let renamed: Option<Vec<(&PkgAttr, String)>> = self.renamed.as_ref().map(|renamed| {
+            renamed
+                .iter()
+                .map(|(old_attr, new_attr)| {
+                    (
+                        new_attr,
+                        format!(
+                            "{} -&gt; {}{}",
+                            html::escape(&old_attr.name),
+                            name(new_attr),
+                            commit(new_attr)
+                        ),
+                    )
+                })
+                .collect()
+        });
+
+        // Only list security-relevant commits if they were looked for
+        if let Some(security) = &self.security {
+            body.push_str(&html::details(
+                "Security-Relevant",
+                &security
+                    .iter()
+                    .map(|val| {
+                        format!(
+                            "{} ({}){}",
+                            html::escape(&val.subject),
+                            html::escape(&val.keywords.join(", ")),
+                            self.sha_link(&val.sha, options)
+                                .map_or("".into(), |(short, url)| {
+                                    format!(" (<a href=\"{}\"><code>{}</code></a>)", url, short)
+                                })
+                        )
+                    })
+                    .collect::<Vec<String>>(),
+            ));
+        }
+
+        for (category, lines) in [
+            ("Added", Some(added)),
+            ("Updated", Some(updated)),
+            ("Removed", Some(removed)),
+            ("Renamed", renamed),
+        ] {
+            if let Some(lines) = lines {
+                body.push_str(&Self::render_html_list(category, &lines, options));
+            }
+        }
+
+        // Only list unparsable commits when there are some
+        if !self.unparsable.is_empty() {
+            body.push_str(&html::details(
+                "Unparsable",
+                &self
+                    .unparsable
+                    .iter()
+                    .map(|val| html::escape(val))
+                    .collect::<Vec<String>>(),
+            ));
+        }
+
+        html::document(&self.heading(options), &body)
+    }
+
+    /// Render a package list as a collapsed section, with a nested section per package set if asked to
+    fn render_html_list(
+        category: &str,
+        lines: &[(&PkgAttr, String)],
+        options: &NixpkgsReportOptions,
+    ) -> String {
+        let items: Vec<String> = lines.iter().map(|(_, line)| line.clone()).collect();
+        if !options.group_by_set || lines.is_empty() {
+            return html::limited_details(category, &items, options.limit);
+        }
+
+        // Only the first entries are grouped if there is a limit, top-level packages (no set) sort first
+        let (kept, hidden) = limit_list(lines, options.limit);
+        let mut groups: BTreeMap<Option<&str>, Vec<String>> = BTreeMap::new();
+        for (attr, line) in kept {
+            groups
+                .entry(attr.set.as_deref())
+                .or_default()
+                .push(line.clone());
+        }
+
+        let mut content: String = groups
+            .iter()
+            .map(|(set, group_lines)| html::details(set.unwrap_or("top-level"), group_lines))
+            .collect();
+        if hidden > 0 {
+            content.push_str(&format!("<p>...and {} more</p>\n", hidden));
+        }
+        html::section(&format!("{} ({})", category, lines.len()), &content)
+    }
+
+    /// Render the changes as a Keep a Changelog release (see template::changelog)
+    pub fn to_changelog(&self, version: &Option<String>) -> String {
+        let added: Vec<String> = self.added.iter().map(|val| val.to_string()).collect();
+        let changed: Vec<String> = self
+            .updated
+            .iter()
+            .map(|val| format!("{}: {}{}", val.name, val.change, val.steps_note()))
+            .chain(
+                self.renamed
+                    .iter()
+                    .flatten()
+                    .map(|(old, new)| format!("{} renamed to {}", old, new)),
+            )
+            .collect();
+        let removed: Vec<String> = self.removed.iter().map(|val| val.to_string()).collect();
+
+        template::changelog(version, &added, &changed, &removed)
+    }
+
+    /// Render a short summary as a Slack or Discord webhook payload. Removals are listed first, then updates with the biggest bumps first, then additions, and they are cut short to fit the message limit.
+    pub fn to_webhook(&self, options: &NixpkgsReportOptions, style: WebhookStyle) -> String {
+        let changes: Vec<String> = self
+            .removed
+            .iter()
+            .map(|val| format!("- {}", val))
+            .chain(
+                self.ordered_updates(UpdateOrder::Bump)
+                    .into_iter()
+                    .map(|val| format!("~ {}: {}{}", val.name, val.change, val.steps_note())),
+            )
+            .chain(self.added.iter().map(|val| format!("+ {}", val)))
+            .collect();
+
+        webhook::payload(style, &self.heading(options), &self.stat_line(), &changes)
+    }
+
+    /// Render the report as pretty printed json
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Unable to serialize nixpkgs report")
+    }
+
+    /// Read a report back from the json to_json made. Reports from a different schema version are refused.
+    pub fn from_json(json: &str) -> Result<NixpkgsReport, ReportParseError> {
+        let value: Value = serde_json::from_str(json)
+            .map_err(|err| ReportParseError::Malformed(err.to_string()))?;
+
+        match value["schema_version"].as_u64() {
+            Some(version) if version == REPORT_SCHEMA_VERSION as u64 => (),
+            Some(version) => return Err(ReportParseError::UnsupportedSchema(version)),
+            None => return Err(ReportParseError::MissingSchema),
+        }
+
+        serde_json::from_value(value).map_err(|err| ReportParseError::Malformed(err.to_string()))
     }
 }
+
+/// Errors produced when a json report can't be read back
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum ReportParseError {
+    /// The json has no schema_version field
+    MissingSchema,
+    /// The report was written with a schema version this build can't read
+    UnsupportedSchema(u64),
+    /// The json is invalid or doesn't have the report layout (holds the serde error)
+    Malformed(String),
+}
+
+impl fmt::Display for ReportParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReportParseError::MissingSchema => write!(f, "report json has no schema_version"),
+            ReportParseError::UnsupportedSchema(version) => write!(
+                f,
+                "report json has schema version {} but only version {} is supported",
+                version, REPORT_SCHEMA_VERSION
+            ),
+            ReportParseError::Malformed(err) => write!(f, "report json is malformed: {}", err),
+        }
+    }
+}
+
+impl error::Error for ReportParseError {}
@@ -1,16 +1,99 @@
 // All of the package structs
 
+use crate::log::trace;
 use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{cmp::Ordering, convert::Infallible, fmt, str::FromStr, sync::LazyLock};
 use time::{Date, macros::format_description};
 
+// --- REGEXES
+/// Splits a version into its epoch, numbers, extra data, and unstable date. The numbers can be left out if there is an unstable date.
+static VERSION_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^(?:(?<epoch>\d+):)?(?:(?<version>\d+(?:\.\d+)*)(?<version_extra>[a-zA-Z0-9]+)?-?)?(?:unstable-(?<unstable_date>\d{4}-\d{2}-\d{2}))?$",
+    )
+    .unwrap()
+});
+
+/// Splits a full package name into its name and version
+static FULL_NAME_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?P<name>.*?)-(?P<version>(?:unstable-)?[0-9][0-9a-zA-Z.:-]*)$").unwrap()
+});
+
 // --- PKG
+/// The stage a version's extra data points to, ordered from oldest to newest
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PreRelease {
+    /// An alpha release and its number (ex. alpha2)
+    Alpha(u64),
+    /// A beta release and its number (ex. beta1)
+    Beta(u64),
+    /// A release candidate and its number (ex. rc5)
+    Rc(u64),
+    /// Extra data that is not a known stage, compared as a string
+    Other(String),
+    /// A patch on top of the release and its number (ex. p3), the only stage newer than the plain release
+    Patch(u64),
+}
+
+impl PreRelease {
+    pub fn new(extra: &str) -> PreRelease {
+        let lower = extra.to_lowercase();
+        let split = lower
+            .find(|val: char| val.is_ascii_digit())
+            .unwrap_or(lower.len());
+        let (stage, number) = lower.split_at(split);
+
+        // A missing number counts as zero (rc < rc1)
+        let number = match number.is_empty() {
+            true => Some(0),
+            false => number.parse::<u64>().ok(),
+        };
+
+        match (stage, number) {
+            ("alpha", Some(number)) => PreRelease::Alpha(number),
+            ("beta", Some(number)) => PreRelease::Beta(number),
+            ("rc", Some(number)) => PreRelease::Rc(number),
+            // A bare p is a patch letter (ex. 1.1.1p), so it stays lexical like the other letters
+            ("p", Some(number)) if split < lower.len() => PreRelease::Patch(number),
+            _ => PreRelease::Other(extra.into()),
+        }
+    }
+}
+
+/// Reads and writes unstable dates as YYYY-MM-DD strings in json
+mod iso_date {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use time::{Date, macros::format_description};
+
+    pub fn serialize<S: Serializer>(date: &Option<Date>, serializer: S) -> Result<S::Ok, S::Error> {
+        date.map(|val| val.to_string()).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Date>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|val| {
+                Date::parse(&val, format_description!("[year]-[month]-[day]"))
+                    .map_err(serde::de::Error::custom)
+            })
+            .transpose()
+    }
+}
+
 /// Version enum for better versioning lookup
-#[derive(PartialEq, Eq, Hash, Clone)]
+#[derive(PartialEq, Eq, Hash, Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum PkgVersion {
-    /// Includes numbered version (0.0.0 etc), extra version data (rc5 etc), and the unstable date if applicable
+    /// Includes the epoch (the 1 in 1:2.0) if there is one, numbered version (0.0.0 etc), extra version data (rc5 etc), and the unstable date if applicable
     Parsed {
-        numbers: Vec<u16>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        epoch: Option<u64>,
+        numbers: Vec<u64>,
         extra: Option<String>,
+        #[serde(with = "iso_date")]
         unstable_date: Option<Date>,
     },
     /// Includes the original string passed (used when version_str can't be parsed)
@@ -18,60 +101,278 @@ pub enum PkgVersion {
 }
 
 impl PkgVersion {
-    pub fn new(version_str: &String) -> PkgVersion {
+    pub fn new(version_str: &str) -> PkgVersion {
         // Try to parse
-        let regex_str = Regex::new(
-            r"^(?<version>\d+(?:\.\d+)*)(?<version_extra>[a-zA-Z0-9]+)?-?(?:unstable-(?<unstable_date>\d{4}-\d{2}-\d{2}))?$",
-        )
-        .unwrap();
+        let captures = VERSION_REGEX.captures(version_str);
 
-        let captures = regex_str.captures(version_str.as_str());
-
-        if let Some(caps) = captures {
-            return PkgVersion::Parsed {
-                numbers: caps
-                    .name("version")
-                    .unwrap()
-                    .as_str()
+        // Everything in the regex is optional, so make sure there is a version or date to go off of
+        if let Some(caps) = captures
+            .filter(|caps| caps.name("version").is_some() || caps.name("unstable_date").is_some())
+        {
+            // Epochs, segments, or dates that are out of range fall through to unparsable
+            let epoch: Option<Option<u64>> = caps
+                .name("epoch")
+                .map(|m| m.as_str().parse::<u64>())
+                .transpose()
+                .ok();
+            // Date-only versions (ex. unstable-2024-01-01) have no numbers
+            let numbers: Option<Vec<u64>> = caps.name("version").map_or(Some(vec![]), |m| {
+                m.as_str()
                     .split(".")
-                    .map(|val| val.parse::<u16>().unwrap())
-                    .collect(),
-                extra: caps.name("version_extra").map(|m| m.as_str().into()),
-                unstable_date: caps.name("unstable_date").map(|m| {
+                    .map(|val| val.parse::<u64>().ok())
+                    .collect()
+            });
+            let unstable_date: Option<Option<Date>> = caps
+                .name("unstable_date")
+                .map(|m| {
                     let format = format_description!("[year]-[month]-[day]");
-                    Date::parse(m.as_str(), &format).unwrap()
-                }),
-            };
+                    Date::parse(m.as_str(), &format)
+                })
+                .transpose()
+                .ok();
+
+            if let (Some(epoch), Some(numbers), Some(unstable_date)) =
+                (epoch, numbers, unstable_date)
+            {
+                trace!(
+                    "version `{}`: epoch {:?}, numbers {:?}, extra {:?}, unstable date {:?}",
+                    version_str,
+                    epoch,
+                    numbers,
+                    caps.name("version_extra").map(|m| m.as_str()),
+                    unstable_date
+                );
+                return PkgVersion::Parsed {
+                    epoch,
+                    numbers,
+                    extra: caps.name("version_extra").map(|m| m.as_str().into()),
+                    unstable_date,
+                };
+            }
+        }
+
+        trace!("version `{}`: no match, unparsable", version_str);
+        PkgVersion::Unparsable(version_str.into())
+    }
+
+    /// Compares two parsed versions by what they mean (1.0 and 1.0.0 are equal here)
+    fn semantic_cmp(&self, other: &PkgVersion) -> Option<Ordering> {
+        match (self, other) {
+            (
+                PkgVersion::Parsed {
+                    epoch,
+                    numbers,
+                    extra,
+                    unstable_date,
+                },
+                PkgVersion::Parsed {
+                    epoch: other_epoch,
+                    numbers: other_numbers,
+                    extra: other_extra,
+                    unstable_date: other_unstable_date,
+                },
+            ) => {
+                // A higher epoch always wins, a missing epoch counts as zero
+                let epoch_ord = epoch.unwrap_or(0).cmp(&other_epoch.unwrap_or(0));
+
+                // Compare segment by segment, missing segments count as zero
+                let numbers_ord = (0..numbers.len().max(other_numbers.len()))
+                    .map(|i| {
+                        numbers
+                            .get(i)
+                            .unwrap_or(&0)
+                            .cmp(other_numbers.get(i).unwrap_or(&0))
+                    })
+                    .find(|ord| ord.is_ne())
+                    .unwrap_or(Ordering::Equal);
+
+                // Extra is a pre-release suffix unless it's a patch, so a version without one sits between the two
+                let extra_ord = match (
+                    extra.as_deref().map(PreRelease::new),
+                    other_extra.as_deref().map(PreRelease::new),
+                ) {
+                    (None, None) => Ordering::Equal,
+                    (None, Some(PreRelease::Patch(_))) => Ordering::Less,
+                    (None, Some(_)) => Ordering::Greater,
+                    (Some(PreRelease::Patch(_)), None) => Ordering::Greater,
+                    (Some(_), None) => Ordering::Less,
+                    (Some(val), Some(other_val)) => val.cmp(&other_val),
+                };
+
+                // Unstable dates order snapshots of the same version, and a dated snapshot is newer than the plain version
+                let unstable_ord = match (unstable_date, other_unstable_date) {
+                    (None, None) => Ordering::Equal,
+                    (None, Some(_)) => Ordering::Less,
+                    (Some(_), None) => Ordering::Greater,
+                    (Some(val), Some(other_val)) => val.cmp(other_val),
+                };
+
+                Some(
+                    epoch_ord
+                        .then(numbers_ord)
+                        .then(extra_ord)
+                        .then(unstable_ord),
+                )
+            }
+            _ => None,
         }
+    }
+
+    /// Finds the direction of a change from this version to the new one. Returns none if either version is unparsable.
+    pub fn direction(&self, new: &PkgVersion) -> Option<VersionDirection> {
+        self.semantic_cmp(new).map(|ord| match ord {
+            Ordering::Less => VersionDirection::Upgrade,
+            Ordering::Greater => VersionDirection::Downgrade,
+            Ordering::Equal => VersionDirection::Lateral,
+        })
+    }
+
+    /// Classifies the change from this version to the new one by the first number segment that differs (missing segments count as zero). A changed epoch is always major.
+    pub fn bump_level(&self, new: &PkgVersion) -> BumpLevel {
+        match (self, new) {
+            (
+                PkgVersion::Parsed { epoch, numbers, .. },
+                PkgVersion::Parsed {
+                    epoch: new_epoch,
+                    numbers: new_numbers,
+                    ..
+                },
+            ) => {
+                if epoch.unwrap_or(0) != new_epoch.unwrap_or(0) {
+                    return BumpLevel::Major;
+                }
+
+                let changed_segment = (0..numbers.len().max(new_numbers.len()))
+                    .find(|&i| numbers.get(i).unwrap_or(&0) != new_numbers.get(i).unwrap_or(&0));
 
-        PkgVersion::Unparsable(version_str.clone())
+                match changed_segment {
+                    Some(0) => BumpLevel::Major,
+                    Some(1) => BumpLevel::Minor,
+                    Some(_) => BumpLevel::Patch,
+                    None => BumpLevel::Other,
+                }
+            }
+            _ => BumpLevel::Unknown,
+        }
     }
+}
 
-    pub fn to_string(&self) -> String {
+impl Ord for PkgVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (
+                PkgVersion::Parsed {
+                    epoch,
+                    numbers,
+                    extra,
+                    ..
+                },
+                PkgVersion::Parsed {
+                    epoch: other_epoch,
+                    numbers: other_numbers,
+                    extra: other_extra,
+                    ..
+                },
+            ) => self
+                .semantic_cmp(other)
+                .unwrap()
+                // Fall back to the raw epoch, segment count, and raw extra data so the order agrees with Eq
+                .then(epoch.cmp(other_epoch))
+                .then(numbers.len().cmp(&other_numbers.len()))
+                .then(extra.cmp(other_extra)),
+            // Unparsable versions always sort last
+            (PkgVersion::Parsed { .. }, PkgVersion::Unparsable(_)) => Ordering::Less,
+            (PkgVersion::Unparsable(_), PkgVersion::Parsed { .. }) => Ordering::Greater,
+            (PkgVersion::Unparsable(val), PkgVersion::Unparsable(other_val)) => val.cmp(other_val),
+        }
+    }
+}
+
+impl PartialOrd for PkgVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for PkgVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            PkgVersion::Unparsable(string) => string.clone(),
+            PkgVersion::Unparsable(string) => write!(f, "{}", string),
             PkgVersion::Parsed {
+                epoch,
                 numbers,
                 extra,
                 unstable_date,
-            } => format!(
-                "{}{}{}",
+            } => write!(
+                f,
+                "{}{}{}{}",
+                epoch.map_or("".into(), |val| format!("{}:", val)),
                 numbers
                     .iter()
                     .map(|val| val.to_string())
                     .collect::<Vec<String>>()
                     .join("."),
-                extra.clone().unwrap_or("".into()),
-                unstable_date
-                    .clone()
-                    .map_or("".into(), |val| val.to_string())
+                extra.as_deref().unwrap_or(""),
+                // Add the unstable marker back so the string parses to the same version
+                unstable_date.map_or("".into(), |val| match numbers.is_empty() {
+                    true => format!("unstable-{}", val),
+                    false => format!("-unstable-{}", val),
+                })
             ),
         }
     }
 }
 
+/// Parsing never fails, versions that can't be parsed become unparsable
+impl FromStr for PkgVersion {
+    type Err = Infallible;
+
+    fn from_str(version_str: &str) -> Result<PkgVersion, Infallible> {
+        Ok(PkgVersion::new(version_str))
+    }
+}
+
+/// The direction a package version moved in
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VersionDirection {
+    /// The new version is newer
+    Upgrade,
+    /// The new version is older
+    Downgrade,
+    /// The versions are equivalent
+    Lateral,
+}
+
+/// How big of a version bump a change was
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BumpLevel {
+    /// The first number segment changed
+    Major,
+    /// The second number segment changed
+    Minor,
+    /// The third (or a later) number segment changed
+    Patch,
+    /// The numbers are the same but the extra data or unstable date changed
+    Other,
+    /// One of the versions is unparsable
+    Unknown,
+}
+
+/// The order updated packages are listed in a report
+#[derive(Default, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum UpdateOrder {
+    /// Alphabetically by name
+    #[default]
+    Name,
+    /// Biggest version bump first (major, minor, patch, other, then unknown), alphabetically within each bump
+    Bump,
+}
+
 /// Individual package data, parsed into data oriented forms
-#[derive(PartialEq, Eq, Hash, Clone)]
+#[derive(PartialEq, Eq, Hash, Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Package {
     /// Includes name, version, and ?description
     Parsed {
@@ -84,22 +385,91 @@ pub enum Package {
 }
 
 impl Package {
-    pub fn new(full_name: &String, description: &Option<String>) -> Package {
+    pub fn new(full_name: &str, description: &Option<String>) -> Package {
         // Try to parse version from name
-        let regex_str =
-            Regex::new(r"^(?P<name>.*?)-(?P<version>(?:unstable-)?[0-9][0-9a-zA-Z.-]*)$").unwrap();
-
-        let captures = regex_str.captures(full_name.as_str());
+        let captures = FULL_NAME_REGEX.captures(full_name);
 
         if let Some(caps) = captures {
+            trace!(
+                "package `{}`: name {} from the full name",
+                full_name, &caps["name"]
+            );
             return Package::Parsed {
                 name: caps.name("name").map(|m| m.as_str().into()).unwrap(),
-                version: PkgVersion::new(&caps.name("version").map(|m| m.as_str().into()).unwrap()),
+                version: PkgVersion::new(caps.name("version").map(|m| m.as_str()).unwrap()),
                 description: description.clone(),
             };
         }
 
-        Package::Unparsable(full_name.clone())
+        trace!("package `{}`: no match, unparsable", full_name);
+        Package::Unparsable(full_name.into())
+    }
+
+    /// Builds a package from a name and a separately given version. The version is cut off the end of the name if the name embeds it (ex. hello-2.12).
+    pub fn new_with_version(
+        full_name: &str,
+        version: &str,
+        description: &Option<String>,
+    ) -> Package {
+        let name = full_name
+            .strip_suffix(version)
+            .and_then(|val| val.strip_suffix('-'))
+            .unwrap_or(full_name);
+
+        trace!(
+            "package `{}`: name {} with version attribute {}",
+            full_name, name, version
+        );
+        Package::Parsed {
+            name: name.into(),
+            version: PkgVersion::new(version),
+            description: description.clone(),
+        }
+    }
+
+    /// Builds a package from a full name and the pname it starts with (ex. pname foo and full name foo-v1.1). Whatever follows the pname is the version, even if it can't be parsed, so the package still keys on its real name. Names that don't start with the pname are parsed like normal.
+    pub fn new_with_pname(full_name: &str, pname: &str, description: &Option<String>) -> Package {
+        let Some(version) = full_name
+            .strip_prefix(pname)
+            .and_then(|val| val.strip_prefix('-'))
+            .filter(|val| !val.is_empty())
+        else {
+            return Package::new(full_name, description);
+        };
+
+        trace!(
+            "package `{}`: name {} from the pname, version {}",
+            full_name, pname, version
+        );
+        Package::Parsed {
+            name: pname.into(),
+            version: PkgVersion::new(version),
+            description: description.clone(),
+        }
+    }
+
+    /// Copies the package under another name (ex. to compare it with the package it maps to)
+    pub fn with_name(&self, new_name: &str) -> Package {
+        match self {
+            Package::Parsed {
+                version,
+                description,
+                ..
+            } => Package::Parsed {
+                name: new_name.into(),
+                version: version.clone(),
+                description: description.clone(),
+            },
+            Package::Unparsable(_) => Package::Unparsable(new_name.into()),
+        }
+    }
+
+    /// Borrows the name of the package
+    pub fn name(&self) -> &str {
+        match self {
+            Package::Parsed { name, .. } => name,
+            Package::Unparsable(name) => name,
+        }
     }
 
     /// Gets the name of the package
@@ -115,9 +485,29 @@ impl Package {
     }
 }
 
+/// Renders the full name the package would be parsed from (ex. hello-2.12)
+impl fmt::Display for Package {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Package::Parsed { name, version, .. } => write!(f, "{}-{}", name, version),
+            Package::Unparsable(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Parses a full name without a description. Parsing never fails, names that can't be parsed become unparsable.
+impl FromStr for Package {
+    type Err = Infallible;
+
+    fn from_str(full_name: &str) -> Result<Package, Infallible> {
+        Ok(Package::new(full_name, &None))
+    }
+}
+
 // --- PKG COMPARE
 /// Holds data produced when two Package objects are compared
-#[derive(PartialEq, Eq, Clone, Debug)]
+#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum PkgCompareData {
     /// The package changed
     Changed {
@@ -127,6 +517,10 @@ pub enum PkgCompareData {
         version_change: Option<bool>,
         /// Did the description change
         description_change: Option<bool>,
+        /// Which way the version moved (none if either version is unparsable)
+        direction: Option<VersionDirection>,
+        /// How big the version bump was (none if the version did not change)
+        bump: Option<BumpLevel>,
     },
     /// The package did not change
     Unchanged,
@@ -141,7 +535,7 @@ impl PkgCompareData {
                     return None;
                 }
 
-                return Some(PkgCompareData::Unchanged);
+                Some(PkgCompareData::Unchanged)
             }
             (
                 Package::Parsed {
@@ -155,11 +549,13 @@ impl PkgCompareData {
                     return None;
                 }
 
-                return Some(PkgCompareData::Changed {
-                    change_string: format!("{}: {} -> unparsable", name, version.to_string()),
+                Some(PkgCompareData::Changed {
+                    change_string: format!("{}: {} -> unparsable", name, version),
                     version_change: None,
                     description_change: None,
-                });
+                    direction: None,
+                    bump: Some(BumpLevel::Unknown),
+                })
             }
             (
                 Package::Unparsable(name),
@@ -173,11 +569,13 @@ impl PkgCompareData {
                     return None;
                 }
 
-                return Some(PkgCompareData::Changed {
-                    change_string: format!("{}: unparsable -> {}", name, version.to_string()),
+                Some(PkgCompareData::Changed {
+                    change_string: format!("{}: unparsable -> {}", name, version),
                     version_change: None,
                     description_change: None,
-                });
+                    direction: None,
+                    bump: Some(BumpLevel::Unknown),
+                })
             }
             (
                 Package::Parsed {
@@ -196,12 +594,18 @@ impl PkgCompareData {
                 }
 
                 if version != new_version || description != new_description {
+                    let direction = version.direction(new_version);
+
                     return Some(PkgCompareData::Changed {
                         change_string: format!(
-                            "{}: {} -> {}{}",
+                            "{}: {} -> {}{}{}",
                             name,
-                            version.to_string(),
-                            new_version.to_string(),
+                            version,
+                            new_version,
+                            match direction {
+                                Some(VersionDirection::Downgrade) => ", downgrade",
+                                _ => "",
+                            },
                             match description != new_description {
                                 true => ", description changed",
                                 false => "",
@@ -209,11 +613,126 @@ impl PkgCompareData {
                         ),
                         version_change: Some(version != new_version),
                         description_change: Some(description != new_description),
+                        direction,
+                        bump: (version != new_version).then(|| version.bump_level(new_version)),
                     });
                 }
 
-                return Some(PkgCompareData::Unchanged);
+                Some(PkgCompareData::Unchanged)
+            }
+        }
+    }
+}
+
+// --- FILTER
+/// Decides which package names are kept. The include pattern is applied first, then the exclude pattern.
+#[derive(Default, Clone, Debug)]
+pub struct NameFilter {
+    /// Only keep names matching this
+    pub include: Option<Regex>,
+    /// Drop names matching this
+    pub exclude: Option<Regex>,
+}
+
+impl NameFilter {
+    /// Checks if the filter drops anything at all
+    pub fn is_active(&self) -> bool {
+        self.include.is_some() || self.exclude.is_some()
+    }
+
+    /// Checks if a name is kept
+    pub fn matches(&self, name: &str) -> bool {
+        self.include.as_ref().is_none_or(|val| val.is_match(name))
+            && self.exclude.as_ref().is_none_or(|val| !val.is_match(name))
+    }
+
+    /// Describes the filter for reports (ex. matching `^lib` and not matching `-unstable$`), none if nothing is filtered
+    pub fn describe(&self) -> Option<String> {
+        let parts: Vec<String> = [
+            self.include
+                .as_ref()
+                .map(|val| format!("matching `{}`", val)),
+            self.exclude
+                .as_ref()
+                .map(|val| format!("not matching `{}`", val)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        (!parts.is_empty()).then(|| parts.join(" and "))
+    }
+}
+
+// --- RENAMES
+/// Counts the single character edits needed to turn one name into another (Levenshtein distance)
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b_chars: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b_chars.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b_char) in b_chars.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = (above + 1)
+                .min(row[j] + 1)
+                .min(diagonal + usize::from(a_char != *b_char));
+            diagonal = above;
+        }
+    }
+
+    row[b_chars.len()]
+}
+
+/// Pairs removed and added items that look like renames (names at most half the longer name apart). Closest names are paired first and each item is used at most once. Paired items are moved out of both lists.
+pub fn pair_renames<T>(
+    removed: &mut Vec<T>,
+    added: &mut Vec<T>,
+    name: impl Fn(&T) -> &str,
+    compatible: impl Fn(&T, &T) -> bool,
+) -> Vec<(T, T)> {
+    // Find every candidate pair with its distance
+    let mut candidates: Vec<(usize, usize, usize)> = vec![];
+    for (i, old) in removed.iter().enumerate() {
+        for (j, new) in added.iter().enumerate() {
+            let (old_name, new_name) = (name(old), name(new));
+            let distance = edit_distance(old_name, new_name);
+            if compatible(old, new)
+                && distance * 2 <= old_name.chars().count().max(new_name.chars().count())
+            {
+                candidates.push((distance, i, j));
             }
         }
     }
+    candidates.sort();
+
+    let mut used_removed = vec![false; removed.len()];
+    let mut used_added = vec![false; added.len()];
+    let mut pairs: Vec<(usize, usize)> = vec![];
+    for (_, i, j) in candidates {
+        if !used_removed[i] && !used_added[j] {
+            used_removed[i] = true;
+            used_added[j] = true;
+            pairs.push((i, j));
+        }
+    }
+
+    // Move the paired items out, keeping the order of the removed list
+    let mut removed_items: Vec<Option<T>> = removed.drain(..).map(Some).collect();
+    let mut added_items: Vec<Option<T>> = added.drain(..).map(Some).collect();
+    pairs.sort();
+    let renames = pairs
+        .iter()
+        .map(|&(i, j)| {
+            (
+                removed_items[i].take().unwrap(),
+                added_items[j].take().unwrap(),
+            )
+        })
+        .collect();
+
+    removed.extend(removed_items.into_iter().flatten());
+    added.extend(added_items.into_iter().flatten());
+    renames
 }
@@ -0,0 +1,166 @@
+// Structs used to compare two saved json nixpkgs reports
+
+use crate::nixpkgs::{NixpkgsReport, NixpkgsUpdate, PkgAttr};
+use std::collections::{BTreeMap, BTreeSet};
+
+// --- LIST DIFF
+/// How one package list changed between two reports
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct ListDiff<T> {
+    /// Entries only in the newer report
+    pub appeared: Vec<T>,
+    /// Entries only in the older report
+    pub disappeared: Vec<T>,
+}
+
+impl<T: Ord + Clone> ListDiff<T> {
+    fn new(old: &[T], new: &[T]) -> ListDiff<T> {
+        let old_set: BTreeSet<&T> = old.iter().collect();
+        let new_set: BTreeSet<&T> = new.iter().collect();
+
+        // Both sets are sorted so the lists come out sorted too
+        ListDiff {
+            appeared: new_set
+                .difference(&old_set)
+                .map(|val| (*val).clone())
+                .collect(),
+            disappeared: old_set
+                .difference(&new_set)
+                .map(|val| (*val).clone())
+                .collect(),
+        }
+    }
+}
+
+// --- REPORT DIFF
+/// The drift between two reports (ex. last week's report and this week's)
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct ReportDiff {
+    /// The range the older report covered as (base, head)
+    old_range: (String, String),
+    /// The range the newer report covered as (base, head)
+    new_range: (String, String),
+    /// Changes to the added list
+    added: ListDiff<PkgAttr>,
+    /// Changes to the updated list, by package name
+    updated: ListDiff<PkgAttr>,
+    /// Packages updated in both reports whose version change differs as (name, old change, new change)
+    changed: Vec<(PkgAttr, String, String)>,
+    /// Changes to the removed list
+    removed: ListDiff<PkgAttr>,
+}
+
+impl ReportDiff {
+    pub fn new(old: &NixpkgsReport, new: &NixpkgsReport) -> ReportDiff {
+        let names = |updated: &[NixpkgsUpdate]| -> Vec<PkgAttr> {
+            updated.iter().map(|val| val.name.clone()).collect()
+        };
+        let old_changes: BTreeMap<&PkgAttr, &String> = old
+            .updated
+            .iter()
+            .map(|val| (&val.name, &val.change))
+            .collect();
+
+        ReportDiff {
+            old_range: (old.base.clone(), old.head.clone()),
+            new_range: (new.base.clone(), new.head.clone()),
+            added: ListDiff::new(&old.added, &new.added),
+            updated: ListDiff::new(&names(&old.updated), &names(&new.updated)),
+            changed: new
+                .updated
+                .iter()
+                .filter_map(|val| {
+                    old_changes
+                        .get(&val.name)
+                        .filter(|old_change| ***old_change != val.change)
+                        .map(|old_change| {
+                            (val.name.clone(), (*old_change).clone(), val.change.clone())
+                        })
+                })
+                .collect(),
+            removed: ListDiff::new(&old.removed, &new.removed),
+        }
+    }
+
+    /// Checks if packages newly showed up in the removed list
+    pub fn has_new_removals(&self) -> bool {
+        !self.removed.appeared.is_empty()
+    }
+
+    /// Generate drift report in markdown
+    pub fn generate_report(&self, title: &Option<String>) -> String {
+        let list = |lines: Vec<String>| {
+            lines
+                .into_iter()
+                .reduce(|mut acc, e| {
+                    acc.push_str(e.as_str());
+                    acc
+                })
+                .unwrap_or("None\n".into())
+        };
+        let section = |heading: &str, diff: &ListDiff<PkgAttr>| {
+            format!(
+                "### {}\n\
+                #### Newly Listed\n\
+                {}\n\
+                #### No Longer Listed\n\
+                {}",
+                heading,
+                list(
+                    diff.appeared
+                        .iter()
+                        .map(|val| format!(" - {}\n", val))
+                        .collect()
+                ),
+                list(
+                    diff.disappeared
+                        .iter()
+                        .map(|val| format!(" - {}\n", val))
+                        .collect()
+                )
+            )
+        };
+
+        format!(
+            "## nix-update-report{}\n\
+            Report generated using [`nix-update-report`](https://github.com/aldenparker/nix-update-report.git).\n\
+            \n\
+            Old Report: `{} -> {}`\n\
+            New Report: `{} -> {}`\n\
+            \n\
+            ### Stats\n\
+            Newly Added: {} (no longer added: {})\n\
+            Newly Updated: {} (no longer updated: {}, changed: {})\n\
+            Newly Removed: {} (no longer removed: {})\n\
+            \n\
+            {}\n\
+            {}\n\
+            #### Changed Version\n\
+            {}\n\
+            {}",
+            title.clone().map_or("".into(), |val| format!(" - {}", val)),
+            self.old_range.0,
+            self.old_range.1,
+            self.new_range.0,
+            self.new_range.1,
+            self.added.appeared.len(),
+            self.added.disappeared.len(),
+            self.updated.appeared.len(),
+            self.updated.disappeared.len(),
+            self.changed.len(),
+            self.removed.appeared.len(),
+            self.removed.disappeared.len(),
+            section("Added", &self.added),
+            section("Updated", &self.updated),
+            list(
+                self.changed
+                    .iter()
+                    .map(|(name, old_change, new_change)| {
+                        format!(" - {}: {} => {}\n", name, old_change, new_change)
+                    })
+                    .collect()
+            ),
+            section("Removed", &self.removed)
+        )
+    }
+}
@@ -0,0 +1,172 @@
+// Simple placeholder templates used to render reports
+
+use std::collections::{BTreeMap, HashMap};
+use time::{OffsetDateTime, macros::format_description};
+
+/// The values a template can use, by placeholder name
+pub type TemplateContext = BTreeMap<&'static str, String>;
+
+/// Fills the `{{name}}` placeholders in a template from the context. Placeholders that are not in the context are left as they are so they are easy to spot.
+pub fn render(template: &str, context: &TemplateContext) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    // Walk the template once so values that hold braces are never expanded again
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+
+        match after.find("}}") {
+            Some(end) => {
+                let name = after[..end].trim();
+                match context.get(name) {
+                    Some(value) => rendered.push_str(value),
+                    None => rendered.push_str(&rest[start..start + 2 + end + 2]),
+                }
+                rest = &after[end + 2..];
+            }
+            None => {
+                rendered.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+
+    rendered.push_str(rest);
+    rendered
+}
+
+/// Reports with fewer headings to link than this (ex. a single arch) are short enough to read without a table of contents
+const TOC_MIN_HEADINGS: usize = 12;
+
+/// Turns a heading into the anchor GitHub links it with: lowercased, with spaces turned into hyphens and everything but letters, numbers, hyphens, and underscores dropped
+pub(crate) fn slug(heading: &str) -> String {
+    heading
+        .trim()
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            '-' | '_' => Some(c),
+            _ if c.is_alphanumeric() => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Adds a table of contents linking every `###` to `#####` heading right before the first `###` heading. Repeated headings are numbered the way GitHub numbers them (ex. #added-1). Reports with fewer than TOC_MIN_HEADINGS of those headings are left as they are.
+pub(crate) fn add_toc(report: &str) -> String {
+    // Every heading counts towards the numbering, even the ones left out of the contents
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    let mut toc: Vec<String> = Vec::new();
+    let mut in_code = false;
+    for line in report.lines() {
+        if line.starts_with("```") {
+            in_code = !in_code;
+        }
+        let level = line.chars().take_while(|c| *c == '#').count();
+        let Some(text) = line[level..].strip_prefix(' ') else {
+            continue;
+        };
+        if in_code || !(1..=6).contains(&level) {
+            continue;
+        }
+
+        let base = slug(text);
+        let count = seen.entry(base.clone()).or_insert(0);
+        let anchor = match *count {
+            0 => base,
+            n => format!("{}-{}", base, n),
+        };
+        *count += 1;
+
+        if (3..=5).contains(&level) {
+            toc.push(format!(
+                "{}- [{}](#{})\n",
+                "  ".repeat(level - 3),
+                text.trim(),
+                anchor
+            ));
+        }
+    }
+
+    match report.find("\n### ") {
+        Some(start) if toc.len() >= TOC_MIN_HEADINGS => format!(
+            "{}### Contents\n{}{}",
+            &report[..start + 1],
+            toc.concat(),
+            &report[start..]
+        ),
+        _ => report.into(),
+    }
+}
+
+/// The version of the tool that generated a report
+pub(crate) const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The current time in UTC for report footers (ex. 2024-01-31 12:00:00 UTC)
+pub(crate) fn generated_at() -> String {
+    OffsetDateTime::now_utc()
+        .format(format_description!(
+            "[year]-[month]-[day] [hour]:[minute]:[second] UTC"
+        ))
+        .expect("Unable to format the current time")
+}
+
+/// The footer ending every markdown report, saying when and with what version it was generated
+pub(crate) fn footer(generated_at: &str) -> String {
+    format!(
+        "\n---\n\
+        Generated by nix-update-report v{} at {}\n",
+        VERSION, generated_at
+    )
+}
+
+/// Renders a Keep a Changelog release with Added, Changed, and Removed sections, leaving out the empty ones. The release is headed with the version and today's date, or Unreleased without a version.
+pub(crate) fn changelog(
+    version: &Option<String>,
+    added: &[String],
+    changed: &[String],
+    removed: &[String],
+) -> String {
+    let heading = match version {
+        Some(version) => format!(
+            "## [{}] - {}\n",
+            version,
+            OffsetDateTime::now_utc()
+                .format(format_description!("[year]-[month]-[day]"))
+                .expect("Unable to format the current date")
+        ),
+        None => "## [Unreleased]\n".into(),
+    };
+
+    [("Added", added), ("Changed", changed), ("Removed", removed)]
+        .iter()
+        .filter(|(_, entries)| !entries.is_empty())
+        .fold(heading, |mut acc, (name, entries)| {
+            acc.push_str(&format!("\n### {}\n", name));
+            for entry in entries.iter() {
+                acc.push_str(&format!("- {}\n", entry));
+            }
+            acc
+        })
+}
+
+/// Renders how much a count grew or shrank with its sign (ex. +3, -1, +0)
+pub(crate) fn net_change(added: usize, removed: usize) -> String {
+    format!("{:+}", added as i64 - removed as i64)
+}
+
+/// Renders a count as a percentage of a total with one decimal (ex. 12.5%), 0.0% if the total is zero
+pub(crate) fn percent(count: usize, total: usize) -> String {
+    match total {
+        0 => "0.0%".into(),
+        _ => format!("{:.1}%", count as f64 * 100.0 / total as f64),
+    }
+}
+
+/// Cuts a list down to the limit, returning the entries kept and how many were cut
+pub(crate) fn limit_list<T>(list: &[T], limit: Option<usize>) -> (&[T], usize) {
+    let kept = limit.map_or(list.len(), |val| val.min(list.len()));
+    (&list[..kept], list.len() - kept)
+}
@@ -0,0 +1,87 @@
+// Compact report messages for Slack and Discord incoming webhooks
+
+use serde_json::json;
+
+/// Room kept at the end of a message for the note saying how many changes were cut (ex. `...and 123 more`)
+const NOTE_RESERVE: usize = 24;
+
+/// The chats a webhook message can be shaped for
+#[derive(Default, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum WebhookStyle {
+    /// A Slack incoming webhook, the message goes in `text`
+    #[default]
+    Slack,
+    /// A Discord webhook, the message goes in `content`
+    Discord,
+}
+
+impl WebhookStyle {
+    /// Guesses the chat from a webhook url. Discord webhooks live on discord.com (or the older discordapp.com), everything else is treated as Slack.
+    pub fn from_url(url: &str) -> WebhookStyle {
+        let host = url
+            .split_once("://")
+            .map_or(url, |(_, rest)| rest)
+            .split(['/', '?', '#'])
+            .next()
+            .unwrap_or("")
+            .to_lowercase();
+        let discord = ["discord.com", "discordapp.com"]
+            .iter()
+            .any(|domain| host == *domain || host.ends_with(&format!(".{}", domain)));
+        match discord {
+            true => WebhookStyle::Discord,
+            false => WebhookStyle::Slack,
+        }
+    }
+
+    /// The most characters a message may hold. Discord rejects messages over 2000 and Slack asks for text to stay well under 4000.
+    pub fn max_len(self) -> usize {
+        match self {
+            WebhookStyle::Slack => 3000,
+            WebhookStyle::Discord => 2000,
+        }
+    }
+
+    /// Wraps a message into the json body the webhook expects
+    pub fn wrap(self, message: &str) -> String {
+        match self {
+            WebhookStyle::Slack => json!({ "text": message }),
+            WebhookStyle::Discord => json!({ "content": message }),
+        }
+        .to_string()
+    }
+}
+
+/// Builds a webhook payload from a heading, a summary line, and one line per change (most important first). The changes go in a code block so package names aren't read as formatting, and are cut short with a count of the ones left out so the message fits the chat's limit.
+pub(crate) fn payload(
+    style: WebhookStyle,
+    heading: &str,
+    summary: &str,
+    changes: &[String],
+) -> String {
+    let mut message = format!("{}\n{}", heading, summary);
+    if changes.is_empty() {
+        return style.wrap(&message);
+    }
+
+    // The opening and closing fences both need a newline
+    let mut used = message.chars().count() + "\n```\n```".len() + NOTE_RESERVE;
+    let mut kept = 0;
+    let mut block = String::new();
+    for change in changes {
+        let len = change.chars().count() + 1;
+        if used + len > style.max_len() {
+            break;
+        }
+        used += len;
+        kept += 1;
+        block.push_str(change);
+        block.push('\n');
+    }
+    if kept < changes.len() {
+        block.push_str(&format!("...and {} more\n", changes.len() - kept));
+    }
+
+    message.push_str(&format!("\n```\n{}```", block));
+    style.wrap(&message)
+}
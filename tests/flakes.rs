@@ -0,0 +1,92 @@
+// Checks how nix flake show output is read into packages
+
+use nix_update_report::{
+    Flake, FlakeCompareData, FlakeCompareOptions, FlakeOptions, FlakeOutput, Package,
+};
+use serde_json::Value;
+use std::collections::HashMap;
+
+#[test]
+fn missing_name_uses_attr_key() {
+    let flake = Flake::from_json_str(
+        r#"{
+            "packages": {
+                "x86_64-linux": {
+                    "ripgrep": { "description": "A fast grep" },
+                    "hello": { "description": "Hello", "version": "2.12" }
+                }
+            }
+        }"#,
+    )
+    .unwrap();
+
+    let json: Value = serde_json::from_str(&flake.to_json()).unwrap();
+    let pkg: Package = serde_json::from_value(json["x86_64-linux"]["ripgrep"].clone()).unwrap();
+    assert_eq!(pkg.name(), "ripgrep");
+
+    // A version attribute is still read when the name is missing
+    let pkg: Package = serde_json::from_value(json["x86_64-linux"]["hello"].clone()).unwrap();
+    assert_eq!(pkg.to_string(), "hello-2.12");
+}
+
+#[test]
+fn mapping_onto_existing_name_is_skipped() {
+    let read = |json: &str| Flake::from_json_str(json).unwrap();
+    let old = read(
+        r#"{ "packages": { "x86_64-linux": {
+            "foo": { "name": "foo-1.0" },
+            "bar": { "name": "bar-2.0" }
+        } } }"#,
+    );
+    let new = read(r#"{ "packages": { "x86_64-linux": { "bar": { "name": "bar-3.0" } } } }"#);
+    let name_map = HashMap::from([("foo".to_string(), "bar".to_string())]);
+    assert_eq!(
+        old.taken_mappings(&name_map),
+        vec![("foo".to_string(), "bar".to_string())]
+    );
+
+    // bar is only compared against the old bar, and foo is still removed
+    let options = FlakeCompareOptions {
+        name_map,
+        ..Default::default()
+    };
+    let compare = FlakeCompareData::new_with_options(&old, &new, &options);
+    assert_eq!(compare.stat_line(), "+0 ~1 -1 across 1 archs");
+    assert_eq!(compare.removal_lines(), vec!["foo removed on x86_64-linux"]);
+
+    // A mapped name the new flake still has is added, since the old one was compared under its new name
+    let new = read(
+        r#"{ "packages": { "x86_64-linux": {
+            "foo": { "name": "foo-1.0" },
+            "foo-ng": { "name": "foo-ng-2.0" },
+            "bar": { "name": "bar-2.0" }
+        } } }"#,
+    );
+    let options = FlakeCompareOptions {
+        name_map: HashMap::from([("foo".to_string(), "foo-ng".to_string())]),
+        include_unchanged: true,
+        ..Default::default()
+    };
+    let compare = FlakeCompareData::new_with_options(&old, &new, &options);
+    assert_eq!(compare.stat_line(), "+1 ~1 -0 across 1 archs");
+}
+
+#[test]
+fn dev_shells_key_on_attr() {
+    let json = r#"{ "devShells": { "x86_64-linux": {
+        "default": { "name": "nix-shell" },
+        "docs": { "name": "nix-shell" }
+    } } }"#;
+    let options = FlakeOptions {
+        output: FlakeOutput::DevShells,
+        ..Default::default()
+    };
+    let flake = Flake::new_with_options(&serde_json::from_str(json).unwrap(), &options).unwrap();
+
+    let json: Value = serde_json::from_str(&flake.to_json()).unwrap();
+    let mut shells: Vec<&String> = json["x86_64-linux"].as_object().unwrap().keys().collect();
+    shells.sort();
+    assert_eq!(shells, ["default", "docs"]);
+    let pkg: Package = serde_json::from_value(json["x86_64-linux"]["docs"].clone()).unwrap();
+    assert_eq!(pkg.name(), "docs");
+}
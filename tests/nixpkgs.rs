@@ -0,0 +1,83 @@
+// Checks how nixpkgs commit messages are parsed and listed in reports
+
+use nix_update_report::{Nixpkgs, NixpkgsReport, NixpkgsReportOptions};
+
+/// Builds commits from one message per line
+fn commits(messages: &[&str]) -> Vec<String> {
+    messages.iter().map(|val| (*val).into()).collect()
+}
+
+/// Collects the report for the commits
+fn report(messages: &[&str]) -> NixpkgsReport {
+    Nixpkgs::new(&commits(messages)).collect_report("NixOS/nixpkgs", "base", "head")
+}
+
+/// Renders the markdown report for the commits, leaving out the footer since it holds the time
+fn markdown(commits: &[String]) -> String {
+    Nixpkgs::new(commits)
+        .collect_report("NixOS/nixpkgs", "base", "head")
+        .to_markdown(&NixpkgsReportOptions::default())
+        .lines()
+        .filter(|line| !line.starts_with("Generated by nix-update-report"))
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+#[test]
+fn added_and_removed_are_sorted() {
+    let commits = commits(&[
+        "zlib-ng: init at 2.1.6",
+        "htop: drop",
+        "aria2: init at 1.37.0",
+        "yq: drop",
+        "mpv: init at 0.38.0",
+        "bat: drop",
+    ]);
+    let report = markdown(&commits);
+    assert_eq!(
+        report,
+        markdown(&commits),
+        "two runs gave different reports"
+    );
+
+    let position = |name: &str| report.find(&format!("- {}", name)).unwrap();
+    assert!(position("aria2") < position("mpv") && position("mpv") < position("zlib-ng"));
+    assert!(position("bat") < position("htop") && position("htop") < position("yq"));
+}
+
+#[test]
+fn multi_package_subjects() {
+    let report = report(&[
+        "foo,bar: 1.0 -> 2.0",
+        "baz: 1.0 -> 2.0, 2.0 -> 3.0",
+        "qux, quux: 1.0 -> 1.1, 5.0 -> 5.1",
+        "a,b,c: 1.0 -> 2.0, 3.0 -> 4.0",
+    ]);
+    let updates: Vec<(String, String)> = report
+        .updated
+        .iter()
+        .map(|val| (val.name.name.clone(), val.change.clone()))
+        .collect();
+    let expected = [
+        ("bar", "1.0 -> 2.0"),
+        ("baz", "1.0 -> 3.0"),
+        ("foo", "1.0 -> 2.0"),
+        ("quux", "5.0 -> 5.1"),
+        ("qux", "1.0 -> 1.1"),
+    ];
+    assert_eq!(
+        updates,
+        expected.map(|(name, change)| (name.to_string(), change.to_string()))
+    );
+
+    // A name with two arrows took both bumps, and three names can't be paired with two arrows
+    assert_eq!(report.updated[1].steps, 2);
+    assert_eq!(report.unparsable, vec!["a,b,c: 1.0 -> 2.0, 3.0 -> 4.0"]);
+}
+
+#[test]
+fn branch_tags_become_badges() {
+    let report = markdown(&commits(&["[24.05] foo: 1.0 -> 1.1", "bar: init at 2.0"]));
+    assert!(report.contains("foo `[24.05]`: 1.0 -> 1.1"), "{}", report);
+    assert!(!report.contains("bar `["));
+}
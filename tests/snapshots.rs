@@ -0,0 +1,131 @@
+// Snapshot tests that render reports from fixed inputs and compare them to the stored markdown in tests/snapshots. Run with UPDATE_SNAPSHOTS=1 to accept new output. This stands in for insta so the tests need no extra dependencies, the snapshots are plain markdown that can be reviewed in a diff.
+
+use nix_update_report::{
+    Flake, FlakeCompareData, FlakeReportOptions, Nixpkgs, NixpkgsReportOptions,
+};
+use std::{env, fs, path::PathBuf};
+
+/// A path inside the tests directory
+fn test_path(path: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join(path)
+}
+
+/// Reads a fixture from tests/fixtures
+fn fixture(path: &str) -> String {
+    let path = test_path(&format!("fixtures/{}", path));
+    fs::read_to_string(&path).unwrap_or_else(|_| panic!("missing fixture {}", path.display()))
+}
+
+/// Compares a report to its stored snapshot, leaving out the footer since it holds the time and version
+fn assert_snapshot(name: &str, report: &str) {
+    let report: String = report
+        .lines()
+        .filter(|line| !line.starts_with("Generated by nix-update-report"))
+        .map(|line| format!("{}\n", line))
+        .collect();
+    let path = test_path(&format!("snapshots/{}.md", name));
+
+    if env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::write(&path, &report).unwrap_or_else(|_| panic!("unable to write {}", path.display()));
+        return;
+    }
+
+    let expected = fs::read_to_string(&path).unwrap_or_else(|_| {
+        panic!(
+            "missing snapshot {}, run with UPDATE_SNAPSHOTS=1 to create it",
+            path.display()
+        )
+    });
+    assert_eq!(
+        expected, report,
+        "snapshot {} changed, run with UPDATE_SNAPSHOTS=1 to accept it",
+        name
+    );
+}
+
+/// Renders the markdown nixpkgs report for a fixture with one commit message per line
+fn nixpkgs_report(path: &str) -> String {
+    let commits: Vec<String> = fixture(path).lines().map(|line| line.into()).collect();
+    Nixpkgs::new(&commits)
+        .collect_report("NixOS/nixpkgs", "base", "head")
+        .to_markdown(&NixpkgsReportOptions::default())
+}
+
+/// Renders the markdown flake report between two fixtures
+fn flake_report(old: &str, new: &str) -> String {
+    flake_report_with_options(old, new, &FlakeReportOptions::default())
+}
+
+/// Renders the markdown flake report between two fixtures using the options given
+fn flake_report_with_options(old: &str, new: &str, options: &FlakeReportOptions) -> String {
+    let read = |path: &str| Flake::from_json_str(&fixture(path)).unwrap();
+    FlakeCompareData::new(&read(old), &read(new)).generate_report_with_options(&None, options)
+}
+
+#[test]
+fn nixpkgs_empty() {
+    assert_snapshot("nixpkgs_empty", &nixpkgs_report("nixpkgs/empty.txt"));
+}
+
+#[test]
+fn nixpkgs_all_added() {
+    assert_snapshot(
+        "nixpkgs_all_added",
+        &nixpkgs_report("nixpkgs/all_added.txt"),
+    );
+}
+
+#[test]
+fn nixpkgs_mixed() {
+    assert_snapshot("nixpkgs_mixed", &nixpkgs_report("nixpkgs/mixed.txt"));
+}
+
+#[test]
+fn flake_empty() {
+    assert_snapshot(
+        "flake_empty",
+        &flake_report("flakes/base.json", "flakes/base.json"),
+    );
+}
+
+#[test]
+fn flake_all_added() {
+    assert_snapshot(
+        "flake_all_added",
+        &flake_report("flakes/subset.json", "flakes/base.json"),
+    );
+}
+
+#[test]
+fn flake_mixed() {
+    assert_snapshot(
+        "flake_mixed",
+        &flake_report("flakes/base.json", "flakes/next.json"),
+    );
+}
+
+#[test]
+fn flake_mixed_toc() {
+    let options = FlakeReportOptions {
+        toc: true,
+        ..Default::default()
+    };
+    assert_snapshot(
+        "flake_mixed_toc",
+        &flake_report_with_options("flakes/base.json", "flakes/next.json", &options),
+    );
+}
+
+#[test]
+fn flake_mixed_matrix() {
+    let options = FlakeReportOptions {
+        matrix: true,
+        ..Default::default()
+    };
+    assert_snapshot(
+        "flake_mixed_matrix",
+        &flake_report_with_options("flakes/base.json", "flakes/next.json", &options),
+    );
+}
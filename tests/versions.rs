@@ -0,0 +1,146 @@
+// Checks that versions print back the way they were written, survive a json round trip, and sort the way nixpkgs means them
+
+use nix_update_report::{Package, PkgVersion};
+use serde_json::{Value, json};
+
+/// Versions that parse and print back unchanged (epochs and versions with every part are checked on their own below)
+const ROUND_TRIPS: [&str; 6] = [
+    "2.13",
+    "1.20250101.0",
+    "1.2rc1",
+    "unstable-2024-01-01",
+    "9.4p1",
+    "not a version",
+];
+
+#[test]
+fn versions_round_trip() {
+    for version in ROUND_TRIPS {
+        let parsed: PkgVersion = version.parse().unwrap();
+        assert_eq!(parsed.to_string(), version);
+    }
+}
+
+#[test]
+fn packages_round_trip() {
+    for name in ["hello-2.13", "foo-1.2rc1-unstable-2024-03-05", "weird"] {
+        let parsed: Package = name.parse().unwrap();
+        assert_eq!(parsed.to_string(), name);
+    }
+}
+
+#[test]
+fn versions_sort_semantically() {
+    let sorted = [
+        "1.0rc1",
+        "1.0",
+        "1.0-unstable-2024-01-01",
+        "1.0p1",
+        "1.1",
+        "1.10",
+        "1:0.1",
+    ];
+    for pair in sorted.windows(2) {
+        let (old, new) = (PkgVersion::new(pair[0]), PkgVersion::new(pair[1]));
+        assert!(old < new, "{} should sort before {}", pair[0], pair[1]);
+    }
+}
+
+#[test]
+fn pname_splits_unreadable_versions() {
+    let pkg = Package::new_with_pname("foo-v1.1", "foo", &None);
+    assert_eq!(pkg.name(), "foo");
+    assert_eq!(pkg.to_string(), "foo-v1.1");
+    assert_eq!(Package::new_with_pname("foo", "foo", &None).name(), "foo");
+}
+
+#[test]
+fn oversized_segments() {
+    // Segments past u16 are still numbers
+    assert_eq!(
+        PkgVersion::new("1.70000.0"),
+        PkgVersion::Parsed {
+            epoch: None,
+            numbers: vec![1, 70000, 0],
+            extra: None,
+            unstable_date: None,
+        }
+    );
+
+    // Segments past u64 can't be held, so the version falls back to unparsable
+    let huge = "1.18446744073709551616";
+    assert_eq!(PkgVersion::new(huge), PkgVersion::Unparsable(huge.into()));
+}
+
+#[test]
+fn display_joins_numbers_extra_and_date() {
+    let version = PkgVersion::new("2.4beta3-unstable-2024-11-30");
+    assert!(matches!(
+        &version,
+        PkgVersion::Parsed { numbers, extra: Some(extra), unstable_date: Some(_), .. }
+            if numbers == &[2, 4] && extra == "beta3"
+    ));
+    assert_eq!(version.to_string(), "2.4beta3-unstable-2024-11-30");
+    assert_eq!(PkgVersion::new(&version.to_string()), version);
+}
+
+#[test]
+fn epochs_sort_first() {
+    assert!(PkgVersion::new("2.0") < PkgVersion::new("1:2.0"));
+    assert!(PkgVersion::new("9.9") < PkgVersion::new("1:0.1"));
+
+    let version = PkgVersion::new("1:2.0");
+    assert!(matches!(version, PkgVersion::Parsed { epoch: Some(1), .. }));
+    assert_eq!(version.to_string(), "1:2.0");
+    assert_eq!(PkgVersion::new(&version.to_string()), version);
+}
+
+#[test]
+fn unstable_dates_sort_by_date() {
+    // Two snapshots of the same version are ordered by date
+    assert!(
+        PkgVersion::new("1.0-unstable-2024-01-01") < PkgVersion::new("1.0-unstable-2024-06-01")
+    );
+    assert!(PkgVersion::new("unstable-2024-01-01") < PkgVersion::new("unstable-2024-06-01"));
+
+    // A dated snapshot is newer than the plain version, but not a higher one
+    assert!(PkgVersion::new("1.0") < PkgVersion::new("1.0-unstable-2024-01-01"));
+    assert!(PkgVersion::new("1.0-unstable-2024-06-01") < PkgVersion::new("1.1"));
+}
+
+#[test]
+fn versions_serde_round_trip() {
+    let version = PkgVersion::new("2:1.4rc2-unstable-2024-03-05");
+    let json: Value = serde_json::to_value(&version).unwrap();
+    assert_eq!(
+        json,
+        json!({ "parsed": {
+            "epoch": 2,
+            "numbers": [1, 4],
+            "extra": "rc2",
+            "unstable_date": "2024-03-05",
+        } })
+    );
+    assert_eq!(serde_json::from_value::<PkgVersion>(json).unwrap(), version);
+
+    let version = PkgVersion::new("not a version");
+    let json: Value = serde_json::to_value(&version).unwrap();
+    assert_eq!(json, json!({ "unparsable": "not a version" }));
+    assert_eq!(serde_json::from_value::<PkgVersion>(json).unwrap(), version);
+}
+
+#[test]
+fn packages_serde_round_trip() {
+    for pkg in [
+        Package::new("hello-2.12-unstable-2024-01-01", &Some("Hello".into())),
+        Package::new("weird", &None),
+    ] {
+        let json = serde_json::to_string(&pkg).unwrap();
+        assert_eq!(serde_json::from_str::<Package>(&json).unwrap(), pkg);
+    }
+
+    let json = serde_json::to_value(Package::new("weird", &None)).unwrap();
+    assert_eq!(json, json!({ "unparsable": "weird" }));
+    let json = serde_json::to_value(Package::new("hello-2.12", &None)).unwrap();
+    assert_eq!(json["parsed"]["name"], "hello");
+}